@@ -3,12 +3,15 @@
 
 use core::net::Ipv4Addr;
 
+use core::mem;
+
 use aya_bpf::{
-	helpers::bpf_ktime_get_ns,
-	bindings::{TC_ACT_PIPE, TC_ACT_SHOT},
-	macros::{classifier, map},
-	maps::HashMap,
-	programs::TcContext
+	helpers::{bpf_ktime_get_ns, bpf_skb_cgroup_id},
+	bindings::{xdp_action, BPF_NOEXIST, TC_ACT_UNSPEC},
+	macros::{cgroup_skb, classifier, map, tracepoint, xdp},
+	maps::{HashMap, LruHashMap},
+	programs::{SkBuffContext, TcContext, TracePointContext, XdpContext},
+	BpfContext,
 };
 use aya_log_ebpf::{trace, debug};
 
@@ -24,49 +27,334 @@ enum PktDirection {
 
 static SUPPORTED_SENT_PROTO: [IpProto; 4] = [IpProto::Tcp, IpProto::Udp, IpProto::Icmp, IpProto::Ipv6Icmp];
 
+// The userspace pins the maps under the directory of the interface with
+// Config::pin, so that they survive the restarts and can be observed.
+// RX, TX, and the last packet sent whatever its destination, which
+// unlike the TX isn't reset by the RX.
+#[map]
+static PKT_TIMESTAMP: HashMap<u8, u64> = HashMap::<u8, u64>::with_max_entries(3, 0);
+
+// Packets seen by the programs, RX and TX like PKT_TIMESTAMP.
+#[map]
+static PKT_COUNT: HashMap<u8, u64> = HashMap::<u8, u64>::with_max_entries(2, 0);
+
+const DNS_PORT: u16 = 53;
+const DNS_HEADER_LEN: usize = 12;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+
+// Same layout as dns_health::Query.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DnsQuery {
+	resolver: u32,
+	port: u16,
+	id: u16,
+}
+
+// Same layout as dns_health::Counters.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DnsCounters {
+	queries: u64,
+	responses: u64,
+	servfail: u64,
+	nxdomain: u64,
+	latency_ns: u64,
+}
+
+// When each DNS query was sent, the userspace removes those which timed out.
+// Not an LRU: the queries it would evict would never be counted as timeouts,
+// those which don't fit aren't counted at all.
+#[map]
+static DNS_PENDING: HashMap<DnsQuery, u64> = HashMap::<DnsQuery, u64>::with_max_entries(1024, 0);
+
+#[map]
+static DNS_STATS: HashMap<u32, DnsCounters> = HashMap::<u32, DnsCounters>::with_max_entries(64, 0);
+
+// Queries of each resolver which timed out, only counted by the userspace
+// but kept (and pinned) along with DNS_STATS.
+#[map]
+static DNS_TIMEOUTS: HashMap<u32, u64> = HashMap::<u32, u64>::with_max_entries(64, 0);
+
+// TCP_STATS keys.
+const TCP_SEGMENTS: u8 = 0;
+const TCP_RETRANSMITS: u8 = 1;
+const TCP_RESETS: u8 = 2;
+
+// Counters of the TCP traffic of the interface.
+#[map]
+static TCP_STATS: HashMap<u8, u64> = HashMap::<u8, u64>::with_max_entries(3, 0);
+
+// IPv4 addresses of the interface, filled by the userspace, the sockets
+// bound to them are the ones going through the interface.
+#[map]
+static IFACE_ADDRS: HashMap<u32, u8> = HashMap::<u32, u8>::with_max_entries(16, 0);
+
+// TP_OFFSETS keys, a field of a tracepoint each.
+const RETRANSMIT_FAMILY: u8 = 0;
+const RETRANSMIT_SADDR: u8 = 1;
+const SET_STATE_OLDSTATE: u8 = 2;
+const SET_STATE_NEWSTATE: u8 = 3;
+const SET_STATE_FAMILY: u8 = 4;
+const SET_STATE_PROTOCOL: u8 = 5;
+const SET_STATE_SADDR: u8 = 6;
+
+// Offsets of the fields the tracepoints read, filled by the userspace from
+// their format in the tracefs as they depend on the kernel.
+#[map]
+static TP_OFFSETS: HashMap<u8, u32> = HashMap::<u8, u32>::with_max_entries(7, 0);
+
+const AF_INET: u16 = 2;
+const IPPROTO_TCP: u16 = 6;
+const TCP_ESTABLISHED: i32 = 1;
+const TCP_SYN_SENT: i32 = 2;
+const TCP_CLOSE: i32 = 7;
+
+// Last RX and TX timestamps of each cgroup, like PKT_TIMESTAMP.
+#[map]
+static CGROUP_TIMESTAMP: LruHashMap<u64, [u64; 2]> = LruHashMap::<u64, [u64; 2]>::with_max_entries(4096, 0);
+
+// Index of the monitored interface, filled by the userspace.
 #[map]
-static PKT_TIMESTAMP: HashMap<u8, u64> = HashMap::<u8, u64>::with_max_entries(2, 0);
+static IFACE_INDEX: HashMap<u8, u32> = HashMap::<u8, u32>::with_max_entries(1, 0);
+
+// Packets of the TC and XDP programs, both starting at the Ethernet header.
+trait Packet: BpfContext {
+	fn load_at<T>(&self, offset: usize) -> Result<T, ()>;
+}
+
+impl Packet for TcContext {
+	fn load_at<T>(&self, offset: usize) -> Result<T, ()> {
+		self.load(offset).map_err(|_| ())
+	}
+}
+
+impl Packet for XdpContext {
+	fn load_at<T>(&self, offset: usize) -> Result<T, ()> {
+		let start = self.data() + offset;
+		// The verifier wants the bounds checked against data_end.
+		if start + mem::size_of::<T>() > self.data_end() {
+			return Err(());
+		}
+		Ok(unsafe { (start as *const T).read_unaligned() })
+	}
+}
 
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo) -> ! {
     unsafe { core::hint::unreachable_unchecked() }
 }
 
-fn try_n_rt_onl_ebpf(ctx: TcContext, dir: PktDirection) -> Result<i32, ()> {
+fn count_dns(resolver: u32, count: impl FnOnce(&mut DnsCounters)) {
+	if unsafe { DNS_STATS.get(&resolver) }.is_none() {
+		let zero = DnsCounters { queries: 0, responses: 0, servfail: 0, nxdomain: 0, latency_ns: 0 };
+		let _ = DNS_STATS.insert(&resolver, &zero, BPF_NOEXIST as u64);
+	}
+	// Updates from other CPUs may be lost, they're only statistics.
+	if let Some(counters) = DNS_STATS.get_ptr_mut(&resolver) {
+		count(unsafe { &mut *counters });
+	}
+}
+
+fn count_tcp(key: u8) {
+	if unsafe { TCP_STATS.get(&key) }.is_none() {
+		let _ = TCP_STATS.insert(&key, &0, BPF_NOEXIST as u64);
+	}
+	// Updates from other CPUs may be lost, they're only statistics.
+	if let Some(count) = TCP_STATS.get_ptr_mut(&key) {
+		unsafe { *count += 1 };
+	}
+}
+
+fn count_pkt(key: u8) {
+	if unsafe { PKT_COUNT.get(&key) }.is_none() {
+		let _ = PKT_COUNT.insert(&key, &0, BPF_NOEXIST as u64);
+	}
+	// Updates from other CPUs may be lost, they're only statistics.
+	if let Some(count) = PKT_COUNT.get_ptr_mut(&key) {
+		unsafe { *count += 1 };
+	}
+}
+
+// Whether the socket with this IPv4 source address goes through the interface.
+fn through_iface(saddr: [u8; 4]) -> bool {
+	unsafe { IFACE_ADDRS.get(&u32::from_be_bytes(saddr)) }.is_some()
+}
+
+// Read a field of the tracepoint, at the offset from TP_OFFSETS.
+fn read_field<T>(ctx: &TracePointContext, field: u8) -> Result<T, i64> {
+	let offset = unsafe { TP_OFFSETS.get(&field) }.ok_or(0)?;
+	unsafe { ctx.read_at(*offset as usize) }
+}
+
+fn try_tcp_retransmit(ctx: &TracePointContext) -> Result<(), i64> {
+	let family: u16 = read_field(ctx, RETRANSMIT_FAMILY)?;
+	let saddr: [u8; 4] = read_field(ctx, RETRANSMIT_SADDR)?;
+	if family == AF_INET && through_iface(saddr) {
+		count_tcp(TCP_RETRANSMITS);
+	}
+
+	Ok(())
+}
+
+// Fired for the sockets of every protocol, not only TCP.
+fn try_tcp_set_state(ctx: &TracePointContext) -> Result<(), i64> {
+	let protocol: u16 = read_field(ctx, SET_STATE_PROTOCOL)?;
+	if protocol != IPPROTO_TCP {
+		return Ok(());
+	}
+	let oldstate: i32 = read_field(ctx, SET_STATE_OLDSTATE)?;
+	let newstate: i32 = read_field(ctx, SET_STATE_NEWSTATE)?;
+	let family: u16 = read_field(ctx, SET_STATE_FAMILY)?;
+	let saddr: [u8; 4] = read_field(ctx, SET_STATE_SADDR)?;
+	// Connections closed without going through the FIN states were reset
+	// (or never established).
+	if family == AF_INET
+		&& newstate == TCP_CLOSE
+		&& (oldstate == TCP_ESTABLISHED || oldstate == TCP_SYN_SENT)
+		&& through_iface(saddr)
+	{
+		count_tcp(TCP_RESETS);
+	}
+
+	Ok(())
+}
+
+#[tracepoint]
+pub fn n_rt_onl_tcp_retransmit(ctx: TracePointContext) -> u32 {
+	let _ = try_tcp_retransmit(&ctx);
+	0
+}
+
+#[tracepoint]
+pub fn n_rt_onl_tcp_set_state(ctx: TracePointContext) -> u32 {
+	let _ = try_tcp_set_state(&ctx);
+	0
+}
+
+// Match the DNS queries sent with the responses received.
+fn handle_dns<C: Packet>(ctx: &C, is_sending: bool, ipv4_hdr: &Ipv4Hdr) -> Result<(), ()> {
+	let vihl: u8 = ctx.load_at(EthHdr::LEN)?;
+	let l4 = EthHdr::LEN + ((vihl & 0x0f) as usize) * 4;
+	let ports: [u8; 4] = ctx.load_at(l4)?;
+	let source = u16::from_be_bytes([ports[0], ports[1]]);
+	let destination = u16::from_be_bytes([ports[2], ports[3]]);
+	if source != DNS_PORT && destination != DNS_PORT {
+		return Ok(());
+	}
+
+	let message = match ipv4_hdr.proto {
+		IpProto::Udp => l4 + 8,
+		IpProto::Tcp => {
+			let offset: u8 = ctx.load_at(l4 + 12)?;
+			let data = l4 + ((offset >> 4) as usize) * 4;
+			let total: [u8; 2] = ctx.load_at(EthHdr::LEN + 2)?;
+			let total = u16::from_be_bytes(total) as usize;
+			let payload = total.checked_sub(data - EthHdr::LEN).ok_or(())?;
+			if payload < 2 + DNS_HEADER_LEN {
+				// Bare ACK, or too short to start a message.
+				return Ok(());
+			}
+			// Over TCP the messages are prefixed with their length, only parse
+			// the segments which start one (as far as we can tell without
+			// following the stream): not longer than the message, and with a
+			// single question like (almost) all of them.
+			let length: [u8; 2] = ctx.load_at(data)?;
+			let length = u16::from_be_bytes(length) as usize;
+			let qdcount: [u8; 2] = ctx.load_at(data + 2 + 4)?;
+			if length < DNS_HEADER_LEN || payload - 2 > length || qdcount != [0, 1] {
+				return Ok(());
+			}
+			data + 2
+		}
+		_ => return Ok(()),
+	};
+	let header: [u8; 4] = ctx.load_at(message)?;
+	let id = u16::from_be_bytes([header[0], header[1]]);
+	let response = header[2] & 0x80 != 0;
+	let rcode = header[3] & 0x0f;
+
+	let now = unsafe { bpf_ktime_get_ns() };
+	if is_sending && !response && destination == DNS_PORT {
+		let resolver = u32::from_be_bytes(ipv4_hdr.dst_addr);
+		let query = DnsQuery { resolver, port: source, id };
+		// Count the queries once if retransmitted, and only those which
+		// fit: the others wouldn't be seen timing out.
+		if DNS_PENDING.insert(&query, &now, BPF_NOEXIST as u64).is_ok() {
+			count_dns(resolver, |c| c.queries += 1);
+		}
+	} else if !is_sending && response && source == DNS_PORT {
+		let resolver = u32::from_be_bytes(ipv4_hdr.src_addr);
+		let query = DnsQuery { resolver, port: destination, id };
+		// Responses to unknown (or timed out) queries are ignored.
+		let sent = match unsafe { DNS_PENDING.get(&query) } {
+			Some(sent) => *sent,
+			None => return Ok(()),
+		};
+		let _ = DNS_PENDING.remove(&query);
+		count_dns(resolver, |c| {
+			c.responses += 1;
+			c.latency_ns += now - sent;
+			match rcode {
+				RCODE_SERVFAIL => c.servfail += 1,
+				RCODE_NXDOMAIN => c.nxdomain += 1,
+				_ => {}
+			}
+		});
+	}
+
+	Ok(())
+}
+
+// Shared by the classifiers and the XDP program (ingress only),
+// which feed the same maps.
+fn try_n_rt_onl_ebpf<C: Packet>(ctx: &C, dir: PktDirection) -> Result<(), ()> {
 	let is_sending = dir == PktDirection::Egress;
-	let eth_hdr: EthHdr = ctx.load(0).map_err(|_| ())?;
+	count_pkt(if is_sending { 1 } else { 0 });
+	let eth_hdr: EthHdr = ctx.load_at(0)?;
 
 	// If the pkt is a Ipv4, continue, otherwise, PASS
     match eth_hdr.ether_type {
         EtherType::Ipv4 => {},
         _ => {
-			trace!(&ctx, "Skipping: not Ipv4");
-			return Ok(TC_ACT_PIPE)
+			trace!(ctx, "Skipping: not Ipv4");
+			return Ok(())
 		},
     }
 
-	let ipv4_hdr: Ipv4Hdr = ctx.load(EthHdr::LEN).map_err(|_| ())?;
+	let ipv4_hdr: Ipv4Hdr = ctx.load_at(EthHdr::LEN)?;
     let source_addr = u32::from_be_bytes(ipv4_hdr.src_addr);
 	let dest_addr = u32::from_be_bytes(ipv4_hdr.dst_addr);
+	if is_sending {
+		let _ = PKT_TIMESTAMP.insert(&2, unsafe { &bpf_ktime_get_ns() }, 0);
+	}
 
 	let ip4_src = Ipv4Addr::from(source_addr);
 	let ip4_dst = Ipv4Addr::from(dest_addr);
 
+	// Before skipping anything, the resolver is often on the LAN.
+	let _ = handle_dns(ctx, is_sending, &ipv4_hdr);
+	// The retransmissions are counted whatever the destination.
+	if is_sending && ipv4_hdr.proto == IpProto::Tcp {
+		count_tcp(TCP_SEGMENTS);
+	}
+
 	// Don't handle pkt if src is private when we're receiving the pkt or if both are private
 	if (ip4_src.is_private() && !is_sending) || (ip4_src.is_private() && ip4_dst.is_private()) {
-		trace!(&ctx, "Skipping: private to private");
-		return Ok(TC_ACT_PIPE);
+		trace!(ctx, "Skipping: private to private");
+		return Ok(());
 	}
 
 	// Don't handle broadcast
 	if ip4_src.is_broadcast() || ip4_dst.is_broadcast() {
-		trace!(&ctx, "Skipping: broadcast");
-		return Ok(TC_ACT_PIPE);
+		trace!(ctx, "Skipping: broadcast");
+		return Ok(());
 	}
 
 	let protocol = ipv4_hdr.proto;
 	if !SUPPORTED_SENT_PROTO.contains(&protocol) {
-		debug!(&ctx, "Unsupported protocol: {}", protocol as u8);
+		debug!(ctx, "Unsupported protocol: {}", protocol as u8);
 	}
 
 	if is_sending && SUPPORTED_SENT_PROTO.contains(&protocol) {
@@ -78,28 +366,83 @@ fn try_n_rt_onl_ebpf(ctx: TcContext, dir: PktDirection) -> Result<i32, ()> {
 	}
 
 	trace!(
-		&ctx,
+		ctx,
 		"{} - Packet: {:i} > {:i}",
 		protocol as u8,
 		source_addr,
 		dest_addr,
 	);
 
-    Ok(TC_ACT_PIPE)
+    Ok(())
+}
+
+// Same analysis as the classifiers, for the cgroup the packet belongs to.
+fn try_n_rt_onl_cgroup(ctx: SkBuffContext, dir: PktDirection) -> Result<(), ()> {
+	let is_sending = dir == PktDirection::Egress;
+	let ifindex = unsafe { (*ctx.skb.skb).ifindex };
+	if unsafe { IFACE_INDEX.get(&0) } != Some(&ifindex) {
+		return Ok(());
+	}
+
+	// The packets start at the IP header.
+	let vihl: u8 = ctx.load(0).map_err(|_| ())?;
+	if vihl >> 4 != 4 {
+		return Ok(());
+	}
+	let ipv4_hdr: Ipv4Hdr = ctx.load(0).map_err(|_| ())?;
+	let ip4_src = Ipv4Addr::from(u32::from_be_bytes(ipv4_hdr.src_addr));
+	let ip4_dst = Ipv4Addr::from(u32::from_be_bytes(ipv4_hdr.dst_addr));
+	if (ip4_src.is_private() && !is_sending) || (ip4_src.is_private() && ip4_dst.is_private()) {
+		return Ok(());
+	}
+	if ip4_src.is_broadcast() || ip4_dst.is_broadcast() {
+		return Ok(());
+	}
+
+	let cgroup = unsafe { bpf_skb_cgroup_id(ctx.skb.skb) };
+	let now = unsafe { bpf_ktime_get_ns() };
+	if is_sending && SUPPORTED_SENT_PROTO.contains(&ipv4_hdr.proto) {
+		let rx = unsafe { CGROUP_TIMESTAMP.get(&cgroup) }.map_or(now, |t| t[0]);
+		let _ = CGROUP_TIMESTAMP.insert(&cgroup, &[rx, now], 0);
+	} else if !is_sending {
+		let _ = CGROUP_TIMESTAMP.insert(&cgroup, &[now, now], 0);
+	}
+
+	Ok(())
+}
+
+#[cgroup_skb]
+pub fn n_rt_onl_cgroup_ingress(ctx: SkBuffContext) -> i32 {
+	let _ = try_n_rt_onl_cgroup(ctx, PktDirection::Ingress);
+	// Never drop anything.
+	1
 }
 
+#[cgroup_skb]
+pub fn n_rt_onl_cgroup_egress(ctx: SkBuffContext) -> i32 {
+	let _ = try_n_rt_onl_cgroup(ctx, PktDirection::Egress);
+	1
+}
+
+// The packets are only looked at: TC_ACT_UNSPEC lets the next filter (or
+// TCX program, for which it's TCX_NEXT) of the interface decide their fate,
+// even when they can't be parsed.
 #[classifier]
 pub fn n_rt_onl_ebpf_ingress(ctx: TcContext) -> i32 {
-    match try_n_rt_onl_ebpf(ctx, PktDirection::Ingress) {
-        Ok(ret) => ret,
-        Err(_) => TC_ACT_SHOT,
-    }
+    let _ = try_n_rt_onl_ebpf(&ctx, PktDirection::Ingress);
+    TC_ACT_UNSPEC
 }
 
 #[classifier]
 pub fn n_rt_onl_ebpf_egress(ctx: TcContext) -> i32 {
-    match try_n_rt_onl_ebpf(ctx, PktDirection::Egress) {
-        Ok(ret) => ret,
-        Err(_) => TC_ACT_SHOT,
-    }
+    let _ = try_n_rt_onl_ebpf(&ctx, PktDirection::Egress);
+    TC_ACT_UNSPEC
+}
+
+// Replaces n_rt_onl_ebpf_ingress with Config::xdp, to timestamp RX before
+// the allocation of the skb.
+#[xdp]
+pub fn n_rt_onl_xdp_ingress(ctx: XdpContext) -> u32 {
+	let _ = try_n_rt_onl_ebpf(&ctx, PktDirection::Ingress);
+	xdp_action::XDP_PASS
 }
\ No newline at end of file
@@ -2,7 +2,7 @@
 extern crate log;
 
 use n_rt_onl::{Config, Onl};
-use std::{env, process};
+use std::{env, process, time::Duration};
 
 #[tokio::main]
 
@@ -18,19 +18,24 @@ async fn main() -> Result<(), anyhow::Error> {
     let iface_name = match env::args().nth(1) {
         Some(n) => n,
         None => {
-            println!("USAGE: basic <NETWORK INTERFACE>");
+            println!("USAGE: basic <NETWORK INTERFACE | auto>");
             process::exit(1);
         }
     };
 
-    let onl = Onl::new(
-        iface_name,
-        Some(Config {
-            icmp_targets: Some(vec![String::from("1.1.1.1")]),
-            icmp_interval: Some(1000),
-            ..Default::default()
-        }),
-    )?;
+    let config = Config {
+        icmp_targets: Some(vec![String::from("1.1.1.1")]),
+        icmp_interval: Some(Duration::from_millis(1000)),
+        ..Default::default()
+    };
+    let onl = match iface_name.as_str() {
+        // Pick the interface carrying the default route, and follow it.
+        "auto" => Onl::for_default_route(Some(Config {
+            follow_default_route: true,
+            ..config
+        }))?,
+        _ => Onl::new(iface_name, Some(config))?,
+    };
     let mut receiver = onl.start()?;
 
     while let Some(e) = receiver.recv().await {
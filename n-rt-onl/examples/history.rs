@@ -0,0 +1,40 @@
+use n_rt_onl::History;
+use std::{
+    env, process,
+    time::{Duration, SystemTime},
+};
+
+/// Print the outages and the availability of each interface
+/// recorded in the journal of Config::history over the last 30 days.
+fn main() -> Result<(), anyhow::Error> {
+    let path = match env::args().nth(1) {
+        Some(p) => p,
+        None => {
+            println!("USAGE: history <JOURNAL>");
+            process::exit(1);
+        }
+    };
+
+    let timeline = History::read(path)?;
+    let to = SystemTime::now();
+    let from = to - Duration::from_secs(30 * 86400);
+    for interface in timeline.interfaces() {
+        let availability = timeline.availability(&interface, from, to).unwrap_or(1.0);
+        println!("{}: {:.3}% available", interface, availability * 100.0);
+        for outage in timeline.outages(Some(&interface), from, to) {
+            println!(
+                "  Down {:?} ago for {:?} ({:?}){}",
+                to.duration_since(outage.start).unwrap_or_default(),
+                outage.duration(),
+                outage.cause,
+                if outage.end.is_none() {
+                    ", ongoing"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+#[macro_use]
+extern crate log;
+
+use n_rt_onl::{Config, Onl};
+use std::{env, process, time::Duration};
+
+/// Monitor the interface and serve its metrics, e.g. with
+/// `curl http://127.0.0.1:9464/metrics`.
+#[tokio::main]
+
+async fn main() -> Result<(), anyhow::Error> {
+    // Define log level
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "INFO")
+    }
+
+    // Init logger/tracing
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!("USAGE: metrics <NETWORK INTERFACE> [ADDRESS]");
+        process::exit(1);
+    }
+
+    let onl = Onl::new(
+        args[1].to_owned(),
+        Some(Config {
+            icmp_targets: Some(vec![String::from("1.1.1.1")]),
+            icmp_interval: Some(Duration::from_millis(1000)),
+            metrics: Some(args.get(2).map_or("127.0.0.1:9464", |a| a).parse()?),
+            ..Default::default()
+        }),
+    )?;
+    let mut receiver = onl.start()?;
+
+    while let Some(e) = receiver.recv().await {
+        info!("Got an event: {:?}", e);
+    }
+
+    Ok(())
+}
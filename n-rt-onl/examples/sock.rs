@@ -9,6 +9,7 @@ use std::{
     os::unix::net::UnixListener,
     process,
     sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
 };
 use tokio::sync::broadcast;
 
@@ -37,7 +38,7 @@ async fn main() -> Result<(), anyhow::Error> {
         iface_name,
         Some(Config {
             icmp_targets: Some(vec![String::from("1.1.1.1")]),
-            icmp_interval: Some(1000),
+            icmp_interval: Some(Duration::from_millis(1000)),
             ..Default::default()
         }),
     )?;
@@ -56,8 +57,8 @@ async fn main() -> Result<(), anyhow::Error> {
             info!("Got an event: {:?}", e);
 
             // Store current value in case new client connect
-            prev_state.store(e.clone() as usize, Ordering::Relaxed);
-            if let Err(e) = tx.send(e) {
+            prev_state.store(e.state.clone() as usize, Ordering::Relaxed);
+            if let Err(e) = tx.send(e.state) {
                 error!("Cannot send broadcast state: {}", e);
                 break;
             }
@@ -0,0 +1,79 @@
+#[cfg(target_os = "linux")]
+#[macro_use]
+extern crate log;
+
+#[cfg(target_os = "linux")]
+use n_rt_onl::{Config, DropPrivileges, Onl};
+use std::process;
+#[cfg(target_os = "linux")]
+use std::{env, time::Duration};
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("Config::drop_privileges is only supported on Linux");
+    process::exit(1);
+}
+
+/// Start as root, drop the privileges, and check the monitor keeps
+/// working: the probes still succeed and the events still come.
+#[cfg(target_os = "linux")]
+#[tokio::main]
+
+async fn main() -> Result<(), anyhow::Error> {
+    // Define log level
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "INFO")
+    }
+
+    // Init logger/tracing
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        println!("USAGE: unprivileged <NETWORK INTERFACE> <USER> [ICMP TARGET]");
+        process::exit(1);
+    }
+
+    let onl = Onl::new(
+        args[1].to_owned(),
+        Some(Config {
+            icmp_targets: Some(vec![args.get(3).cloned().unwrap_or("1.1.1.1".into())]),
+            icmp_interval: Some(Duration::from_millis(500)),
+            drop_privileges: Some(DropPrivileges {
+                user: args[2].to_owned(),
+                group: None,
+                seccomp: true,
+            }),
+            ..Default::default()
+        }),
+    )?;
+    let mut results = onl.probe_results();
+    let mut receiver = onl.start()?;
+
+    info!("Running as uid {}", unsafe { libc::getuid() });
+    // Denied by the seccomp filter.
+    if let Ok(status) = process::Command::new("true").status() {
+        error!("could still execute a program: {}", status);
+        process::exit(1);
+    }
+
+    let mut succeeded = 0;
+    while succeeded < 5 {
+        tokio::select! {
+            Ok(result) = results.recv() => {
+                info!("Probe: {:?}", result);
+                if result.rtt.is_some() {
+                    succeeded += 1;
+                }
+            }
+            Some(e) = receiver.recv() => info!("Got an event: {:?}", e),
+            _ = tokio::time::sleep(Duration::from_secs(10)) => {
+                error!("no probe result for 10s");
+                process::exit(1);
+            }
+        }
+    }
+    info!("Still monitoring without privileges");
+
+    Ok(())
+}
@@ -1,41 +1,866 @@
 #[macro_use]
 extern crate log;
 
-use n_rt_onl::{Config, Onl};
-use std::{env, process};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
-#[tokio::main]
+use anyhow::{anyhow, bail};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use n_rt_onl::{
+    Config, DnsMonitor, History, Onl, PortalCheck, ProbeKind, ProbeTarget, Quorum, Report,
+    ReportFormat, State, TcpMonitor,
+};
+#[cfg(target_os = "linux")]
+use n_rt_onl::{DropPrivileges, Netns};
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+use n_rt_onl::{TcAttach, TcMode, XdpMode};
+use serde::{de, Deserialize, Deserializer};
 
-async fn main() -> Result<(), anyhow::Error> {
-    // Define log level
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", "TRACE,fastping_rs=INFO")
+/// Detect the network outages of an interface.
+#[derive(Debug, Parser)]
+#[command(name = "n-rt-onl", version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// TOML file of options (the same as on the command line, with underscores),
+    /// those given on the command line take precedence.
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    watch: Run,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Monitor the interface and print the events (the default).
+    Watch(Run),
+    /// Print the state of the interface once known, exit with 0 if it's Up.
+    Status(Run),
+    /// Check what the options need from the machine, without starting anything.
+    Doctor(Run),
+    /// Availability of the interfaces from the journal of the history option.
+    Report(ReportArgs),
+}
+
+#[derive(Debug, Args)]
+struct Run {
+    /// Interface to monitor, auto for the one carrying the default route.
+    interface: Option<String>,
+    #[command(flatten)]
+    options: Options,
+}
+
+#[derive(Debug, Args)]
+struct ReportArgs {
+    /// Journal to read, default to the history option.
+    journal: Option<PathBuf>,
+    /// Only report this interface.
+    interface: Option<String>,
+    /// Report the last days.
+    #[arg(long, default_value_t = 30, conflicts_with = "from")]
+    days: u64,
+    /// First day (UTC) reported, YYYY-MM-DD.
+    #[arg(long, value_parser = Report::parse_date)]
+    from: Option<SystemTime>,
+    /// Day (UTC) the report stops at, excluded. Default to now.
+    #[arg(long, value_parser = Report::parse_date)]
+    to: Option<SystemTime>,
+    /// table, json or csv.
+    #[arg(long, default_value = "table")]
+    format: ReportFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    Ebpf,
+    Userspace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Output {
+    /// Logged.
+    Text,
+    /// One JSON object per line on stdout.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TcModeArg {
+    Auto,
+    Tcx,
+    Netlink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum XdpModeArg {
+    Auto,
+    Native,
+    Skb,
+}
+
+/// A duration with its unit, e.g. 500ms, 1.5s, 2m or 1h.
+#[derive(Debug, Clone, Copy)]
+struct Dur(Duration);
+
+impl FromStr for Dur {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow!(
+                "invalid duration {:?}, expected e.g. 500ms, 1.5s, 2m or 1h",
+                s
+            )
+        };
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let value: f64 = s[..split].parse().map_err(|_| invalid())?;
+        let secs = match &s[split..] {
+            "ms" => value / 1000.0,
+            "s" => value,
+            "m" => value * 60.0,
+            "h" => value * 3600.0,
+            _ => return Err(invalid()),
+        };
+
+        Duration::try_from_secs_f64(secs)
+            .map(Dur)
+            .map_err(|_| invalid())
+    }
+}
+
+/// icmp:IP, tcp:IP:PORT, dns:IP[:PORT] or a http:// URL.
+#[derive(Debug, Clone)]
+struct Probe(ProbeKind);
+
+impl FromStr for Probe {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") {
+            return Ok(Probe(ProbeKind::Http(s.to_owned())));
+        }
+        let invalid = |e: String| {
+            anyhow!(
+                "invalid probe {:?}{}, expected icmp:IP, tcp:IP:PORT, dns:IP[:PORT] or a http:// URL",
+                s,
+                e
+            )
+        };
+        let (kind, target) = s.split_once(':').ok_or_else(|| invalid(String::new()))?;
+        let kind = match kind {
+            "icmp" => target.parse().map(ProbeKind::Icmp),
+            "tcp" => target.parse().map(ProbeKind::Tcp),
+            "dns" => target
+                .parse()
+                .or_else(|_| target.parse().map(|ip| SocketAddr::new(ip, 53)))
+                .map(ProbeKind::Dns),
+            _ => return Err(invalid(String::new())),
+        };
+
+        kind.map(Probe).map_err(|e| invalid(format!(" ({})", e)))
     }
+}
 
-    // Init logger/tracing
-    tracing_subscriber::fmt::init();
+/// UP/DOWN[/WINDOW], see Quorum.
+#[derive(Debug, Clone)]
+struct QuorumArg(u32, u32, Option<Duration>);
 
-    let iface_name = match env::args().nth(1) {
-        Some(n) => n,
-        None => {
-            println!("USAGE: onl <NETWORK INTERFACE>");
-            process::exit(1);
+impl FromStr for QuorumArg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow!(
+                "invalid quorum {:?}, expected UP/DOWN[/WINDOW], e.g. 2/2/10s",
+                s
+            )
+        };
+        let mut parts = s.split('/');
+        let up = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        let down = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        let window = parts.next().map(Dur::from_str).transpose()?.map(|d| d.0);
+        if up == 0 || down == 0 || parts.next().is_some() {
+            return Err(invalid());
         }
+
+        Ok(QuorumArg(up, down, window))
+    }
+}
+
+/// Deserialize a string with the FromStr of the type, for its errors.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn parsed_vec<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    let strings = Vec::<String>::deserialize(deserializer)?;
+    strings
+        .iter()
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// The fields of Config, the defaults are the ones of Config.
+#[derive(Debug, Default, Args, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Options {
+    /// Only in the config file, the interface when none is given.
+    #[arg(skip)]
+    interface: Option<String>,
+    /// How the events are printed.
+    #[arg(long, value_enum)]
+    output: Option<Output>,
+    /// Backend expected, fails if this build has the other one (see the userspace feature).
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+    /// Path to the eBPF program.
+    #[arg(long)]
+    ebpf_prog_path: Option<String>,
+    /// Max time between the RX and TX packets [default: 1500ms].
+    #[arg(long)]
+    #[serde(default, deserialize_with = "parsed")]
+    rxtx_threshold: Option<Dur>,
+    /// Address pinged in the background, can be repeated [default: 1.1.1.1,
+    /// an empty list in the config file disables it].
+    #[arg(long = "icmp-target")]
+    icmp_targets: Option<Vec<IpAddr>>,
+    /// Time between the pings [default: 1s].
+    #[arg(long)]
+    #[serde(default, deserialize_with = "parsed")]
+    icmp_interval: Option<Dur>,
+    /// Target probed in the background: icmp:IP, tcp:IP:PORT, dns:IP[:PORT]
+    /// or a http:// URL, can be repeated.
+    #[arg(long = "probe")]
+    #[serde(default, deserialize_with = "parsed_vec")]
+    probes: Option<Vec<Probe>>,
+    /// Time between the probes [default: 1s].
+    #[arg(long)]
+    #[serde(default, deserialize_with = "parsed")]
+    probe_interval: Option<Dur>,
+    /// Time after which a probe failed [default: 1s].
+    #[arg(long)]
+    #[serde(default, deserialize_with = "parsed")]
+    probe_timeout: Option<Dur>,
+    /// Decide the state from the probes instead of the RX/TX packets.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    probe_driven: Option<bool>,
+    /// Weight of the targets to be Up and Down with probe_driven: UP/DOWN[/WINDOW].
+    #[arg(long)]
+    #[serde(default, deserialize_with = "parsed")]
+    quorum: Option<QuorumArg>,
+    /// Send the probes through the interface [default: true].
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    bind_probes: Option<bool>,
+    /// Watch the carrier and the routes with rtnetlink (Linux) [default: true].
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    link_monitor: Option<bool>,
+    /// Switch to the interface carrying the default route whenever it moves.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    follow_default_route: Option<bool>,
+    /// Find where the outage is when going Down.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    localize: Option<bool>,
+    /// DNS resolver probed by localize [default: the one of /etc/resolv.conf].
+    #[arg(long)]
+    dns_resolver: Option<SocketAddr>,
+    /// Probe the gateway with ARP or NDP at this interval.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "parsed")]
+    gateway_probe_interval: Option<Dur>,
+    /// Gateway to probe [default: the one of the default route].
+    #[arg(long)]
+    gateway: Option<IpAddr>,
+    /// Connectivity check telling whether we're behind a captive portal,
+    /// a http:// URL [default: the one of PortalCheck].
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "default")]
+    captive_portal: Option<String>,
+    /// Status of the connectivity check when nothing intercepts it [default: 204].
+    #[arg(long)]
+    captive_portal_status: Option<u16>,
+    /// Keep statistics of the DNS traffic and report when it degrades.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    dns_monitor: Option<bool>,
+    /// Estimate the loss from the TCP retransmissions (eBPF).
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    tcp_monitor: Option<bool>,
+    /// Journal the transitions are appended to.
+    #[arg(long)]
+    history: Option<PathBuf>,
+    /// Serve the Prometheus metrics at this address (metrics feature).
+    #[arg(long)]
+    metrics: Option<SocketAddr>,
+    /// Network namespace of the interface, a name of ip netns or a path (Linux).
+    #[arg(long)]
+    netns: Option<String>,
+    /// Switch to this USER[:GROUP] once started (Linux).
+    #[arg(long)]
+    drop_privileges: Option<String>,
+    /// With drop_privileges, deny the syscalls never needed afterwards.
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    seccomp: Option<bool>,
+    /// Root of the cgroup v2 hierarchy (eBPF).
+    #[arg(long)]
+    cgroup_root: Option<PathBuf>,
+    /// Pin the maps under /sys/fs/bpf/n-rt-onl (eBPF).
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pin: Option<bool>,
+    /// Only read the maps pinned by another process (eBPF).
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    observer: Option<bool>,
+    /// How the classifiers are attached (eBPF) [default: auto].
+    #[arg(long, value_enum)]
+    tc_mode: Option<TcModeArg>,
+    /// Priority of the netlink filters, 0 lets the kernel choose (eBPF).
+    #[arg(long)]
+    tc_priority: Option<u16>,
+    /// Handle of the netlink filters, 0 lets the kernel choose (eBPF).
+    #[arg(long)]
+    tc_handle: Option<u32>,
+    /// Timestamp the received packets with XDP (eBPF).
+    #[arg(long, value_enum)]
+    xdp: Option<XdpModeArg>,
+}
+
+/// The options of `cli`, then those of `file` for the fields not set.
+macro_rules! or {
+    ($cli:ident, $file:ident, $($field:ident),* $(,)?) => {
+        Options { $($field: $cli.$field.or($file.$field)),* }
     };
+}
 
-    let onl = Onl::new(
-        iface_name,
-        Some(Config {
-            icmp_targets: Some(vec![String::from("1.1.1.1")]),
-            icmp_interval: Some(1000),
-            ..Default::default()
-        }),
-    )?;
-    let mut receiver = onl.start()?;
+impl Options {
+    fn or(self, file: Options) -> Options {
+        or!(
+            self,
+            file,
+            interface,
+            output,
+            backend,
+            ebpf_prog_path,
+            rxtx_threshold,
+            icmp_targets,
+            icmp_interval,
+            probes,
+            probe_interval,
+            probe_timeout,
+            probe_driven,
+            quorum,
+            bind_probes,
+            link_monitor,
+            follow_default_route,
+            localize,
+            dns_resolver,
+            gateway_probe_interval,
+            gateway,
+            captive_portal,
+            captive_portal_status,
+            dns_monitor,
+            tcp_monitor,
+            history,
+            metrics,
+            netns,
+            drop_privileges,
+            seccomp,
+            cgroup_root,
+            pin,
+            observer,
+            tc_mode,
+            tc_priority,
+            tc_handle,
+            xdp,
+        )
+    }
 
-    while let Some(e) = receiver.recv().await {
-        info!("Got an event: {:?}", e);
+    fn load(path: &Path) -> Result<Options, anyhow::Error> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))
     }
 
+    /// Build the Config, rejecting what this build doesn't support.
+    fn config(&self) -> Result<Config, anyhow::Error> {
+        let mut config = Config::default();
+
+        let built = if cfg!(all(target_os = "linux", not(feature = "userspace"))) {
+            Backend::Ebpf
+        } else {
+            Backend::Userspace
+        };
+        if let Some(backend) = self.backend.filter(|b| *b != built) {
+            let name = |b: Backend| b.to_possible_value().unwrap().get_name().to_owned();
+            bail!(
+                "backend: this build has the {} backend, not {} (see the userspace feature)",
+                name(built),
+                name(backend)
+            );
+        }
+
+        let nonzero = |name: &str, d: Option<Dur>| match d {
+            Some(Dur(d)) if d.is_zero() => Err(anyhow!("{} can't be 0", name)),
+            d => Ok(d.map(|d| d.0)),
+        };
+        if let Some(threshold) = nonzero("rxtx_threshold", self.rxtx_threshold)? {
+            config.rxtx_threshold = threshold;
+        }
+        // Some traffic keeps the RX/TX timestamps fresh on idle hosts.
+        config.icmp_targets = match &self.icmp_targets {
+            Some(targets) if targets.is_empty() => None,
+            Some(targets) => Some(targets.iter().map(|ip| ip.to_string()).collect()),
+            None => Some(vec![String::from("1.1.1.1")]),
+        };
+        config.icmp_interval =
+            Some(nonzero("icmp_interval", self.icmp_interval)?.unwrap_or(Duration::from_secs(1)));
+
+        let interval = nonzero("probe_interval", self.probe_interval)?;
+        let timeout = nonzero("probe_timeout", self.probe_timeout)?;
+        for Probe(kind) in self.probes.iter().flatten() {
+            let mut target = ProbeTarget::new(kind.clone());
+            target.interval = interval.unwrap_or(target.interval);
+            target.timeout = timeout.unwrap_or(target.timeout);
+            config.probes.push(target);
+        }
+        config.probe_driven = self.probe_driven.unwrap_or(config.probe_driven);
+        if let Some(QuorumArg(up, down, window)) = &self.quorum {
+            config.quorum = Some(Quorum {
+                up: *up,
+                down: *down,
+                window: window.unwrap_or(config.rxtx_threshold),
+            });
+        }
+        config.bind_probes = self.bind_probes.unwrap_or(config.bind_probes);
+
+        config.follow_default_route = self
+            .follow_default_route
+            .unwrap_or(config.follow_default_route);
+        config.localize = self.localize.unwrap_or(config.localize);
+        config.dns_resolver = self.dns_resolver;
+        config.gateway_probe_interval =
+            nonzero("gateway_probe_interval", self.gateway_probe_interval)?;
+        config.gateway = self.gateway;
+
+        if let Some(url) = &self.captive_portal {
+            let mut check = PortalCheck::default();
+            if url != "default" {
+                if !url.starts_with("http://") {
+                    bail!("captive_portal: {:?} isn't a http:// URL", url);
+                }
+                check.url = url.clone();
+            }
+            check.status = self.captive_portal_status.unwrap_or(check.status);
+            // Only the empty responses are expected to be the same each time.
+            check.body = (check.status == 204).then(String::new);
+            config.captive_portal = Some(check);
+        } else if self.captive_portal_status.is_some() {
+            bail!("captive_portal_status needs captive_portal");
+        }
+        if self.dns_monitor == Some(true) {
+            config.dns_monitor = Some(DnsMonitor::default());
+        }
+        if self.tcp_monitor == Some(true) {
+            config.tcp_monitor = Some(TcpMonitor::default());
+        }
+        config.history = self.history.clone();
+
+        #[cfg(feature = "metrics")]
+        {
+            config.metrics = self.metrics;
+        }
+        #[cfg(not(feature = "metrics"))]
+        if self.metrics.is_some() {
+            bail!("metrics: this build doesn't have the metrics feature");
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            config.link_monitor = self.link_monitor.unwrap_or(config.link_monitor);
+            config.netns = self.netns.as_ref().map(|ns| match ns.contains('/') {
+                true => Netns::Path(PathBuf::from(ns)),
+                false => Netns::Named(ns.clone()),
+            });
+            config.drop_privileges = self.drop_privileges.as_ref().map(|user| {
+                let (user, group) = match user.split_once(':') {
+                    Some((user, group)) => (user, Some(group.to_owned())),
+                    None => (user.as_str(), None),
+                };
+                DropPrivileges {
+                    user: user.to_owned(),
+                    group,
+                    seccomp: self.seccomp.unwrap_or(false),
+                }
+            });
+            if self.seccomp.is_some() && self.drop_privileges.is_none() {
+                bail!("seccomp needs drop_privileges");
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        for (name, set) in [
+            ("link_monitor", self.link_monitor.is_some()),
+            ("netns", self.netns.is_some()),
+            ("drop_privileges", self.drop_privileges.is_some()),
+            ("seccomp", self.seccomp.is_some()),
+        ] {
+            if set {
+                bail!("{} is only supported on Linux", name);
+            }
+        }
+
+        #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+        {
+            if let Some(path) = &self.ebpf_prog_path {
+                config.ebpf_prog_path = path.clone();
+            }
+            if let Some(root) = &self.cgroup_root {
+                config.cgroup_root = root.clone();
+            }
+            config.pin = self.pin.unwrap_or(config.pin);
+            config.observer = self.observer.unwrap_or(config.observer);
+            config.tc_attach = TcAttach {
+                mode: match self.tc_mode {
+                    None | Some(TcModeArg::Auto) => TcMode::Auto,
+                    Some(TcModeArg::Tcx) => TcMode::Tcx,
+                    Some(TcModeArg::Netlink) => TcMode::Netlink,
+                },
+                priority: self.tc_priority.unwrap_or_default(),
+                handle: self.tc_handle.unwrap_or_default(),
+            };
+            config.xdp = self.xdp.map(|mode| match mode {
+                XdpModeArg::Auto => XdpMode::Auto,
+                XdpModeArg::Native => XdpMode::Native,
+                XdpModeArg::Skb => XdpMode::Skb,
+            });
+        }
+        #[cfg(any(feature = "userspace", not(target_os = "linux")))]
+        for (name, set) in [
+            ("ebpf_prog_path", self.ebpf_prog_path.is_some()),
+            ("cgroup_root", self.cgroup_root.is_some()),
+            ("pin", self.pin.is_some()),
+            ("observer", self.observer.is_some()),
+            ("tc_mode", self.tc_mode.is_some()),
+            ("tc_priority", self.tc_priority.is_some()),
+            ("tc_handle", self.tc_handle.is_some()),
+            ("xdp", self.xdp.is_some()),
+        ] {
+            if set {
+                bail!("{} is only supported by the eBPF backend", name);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// The interface and Config of the command.
+fn settings(run: Run, file: Options) -> Result<(String, Option<Output>, Config), anyhow::Error> {
+    let options = run.options.or(file);
+    let interface = run.interface.or(options.interface.clone()).ok_or_else(|| {
+        anyhow!(
+            "no interface given, as argument or in the config file (auto for the default route)"
+        )
+    })?;
+
+    Ok((interface, options.output, options.config()?))
+}
+
+fn start(interface: String, config: Config) -> Result<Onl, anyhow::Error> {
+    match interface.as_str() {
+        // Pick the interface carrying the default route, and follow it.
+        "auto" => Onl::for_default_route(Some(Config {
+            follow_default_route: true,
+            ..config
+        })),
+        _ => Onl::new(interface, Some(config)),
+    }
+}
+
+/// The time `days` before `to`.
+fn days_before(to: SystemTime, days: u64) -> Result<SystemTime, anyhow::Error> {
+    days.checked_mul(86400)
+        .and_then(|secs| to.checked_sub(Duration::from_secs(secs)))
+        .ok_or_else(|| anyhow!("days: {} days back is out of range", days))
+}
+
+fn report(args: ReportArgs, file: Options) -> Result<(), anyhow::Error> {
+    let Some(journal) = args.journal.or(file.history) else {
+        bail!("no journal given, as argument or as history in the config file");
+    };
+    let to = args.to.unwrap_or_else(SystemTime::now);
+    let from = match args.from {
+        Some(from) => from,
+        None => days_before(to, args.days)?,
+    };
+
+    let timeline = History::read(journal)?;
+    let interfaces = match args.interface {
+        Some(interface) => vec![interface],
+        None => timeline.interfaces(),
+    };
+    let reports: Vec<Report> = interfaces
+        .iter()
+        .map(|interface| timeline.report(interface, from, to))
+        .collect();
+    print!("{}", Report::render(&reports, args.format)?);
+
     Ok(())
 }
+
+#[tokio::main]
+
+async fn main() -> Result<ExitCode, anyhow::Error> {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Watch(cli.watch));
+
+    // Define log level, only the monitoring is verbose.
+    if std::env::var("RUST_LOG").is_err() {
+        let level = match command {
+            Command::Watch(_) => "TRACE",
+            _ => "WARN",
+        };
+        std::env::set_var("RUST_LOG", level)
+    }
+
+    // Init logger/tracing, stdout is left to the output.
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let file = match &cli.config {
+        Some(path) => Options::load(path)?,
+        None => Options::default(),
+    };
+
+    match command {
+        Command::Watch(run) => {
+            let (interface, output, config) = settings(run, file)?;
+            let mut receiver = start(interface, config)?.start()?;
+
+            loop {
+                let e = tokio::select! {
+                    e = receiver.recv() => e,
+                    // Stop the monitor, which records the end of the monitoring.
+                    _ = tokio::signal::ctrl_c() => None,
+                };
+                let Some(e) = e else { break };
+                match output {
+                    Some(Output::Json) => println!("{}", serde_json::to_string(&e)?),
+                    _ => info!("Got an event: {:?}", e),
+                }
+            }
+        }
+        Command::Status(run) => {
+            let (interface, output, config) = settings(run, file)?;
+            // Time for the analysis to start, and to probe if it decides.
+            let timeout = config.rxtx_threshold * 4 + Duration::from_secs(5);
+            let mut receiver = start(interface.clone(), config)?.start()?;
+
+            let event = tokio::time::timeout(timeout, async {
+                while let Some(e) = receiver.recv().await {
+                    if e.state != State::Ukn {
+                        return Some(e);
+                    }
+                }
+                None
+            })
+            .await
+            .ok()
+            .flatten();
+            let up = event.as_ref().is_some_and(|e| e.state == State::Up);
+            match (output, event) {
+                (Some(Output::Json), event) => println!("{}", serde_json::to_string(&event)?),
+                (_, Some(e)) => match e.cause {
+                    Some(cause) => println!("{}: {:?} ({:?})", interface, e.state, cause),
+                    None => println!("{}: {:?}", interface, e.state),
+                },
+                (_, None) => println!("{}: {:?}", interface, State::Ukn),
+            }
+            // Stop the monitor (detaching the classifiers) before exiting.
+            drop(receiver);
+            if !up {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        // Tell what will and won't work, without starting anything.
+        Command::Doctor(run) => {
+            let (interface, _, config) = settings(run, file)?;
+            let report = Onl::preflight(&interface, &config);
+            print!("{}", report);
+            if !report.ok() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Report(args) => report(args, file)?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    fn dur(s: &str) -> Result<Duration, anyhow::Error> {
+        s.parse::<Dur>().map(|d| d.0)
+    }
+
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(dur("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(dur("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(dur("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(dur("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(dur("0s").unwrap(), Duration::ZERO);
+
+        for invalid in ["", "1", "s", "-1s", "1d", "1.2.3s", "1 s", "1e3s"] {
+            assert!(dur(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn durations_out_of_range() {
+        assert!(dur("99999999999999999999999h").is_err());
+        assert!(dur(&format!("{}s", f64::MAX)).is_err());
+    }
+
+    #[test]
+    fn probes() {
+        let probe = |s: &str| s.parse::<Probe>().map(|p| p.0);
+        assert_eq!(
+            probe("icmp:1.1.1.1").unwrap(),
+            ProbeKind::Icmp("1.1.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            probe("tcp:1.1.1.1:443").unwrap(),
+            ProbeKind::Tcp("1.1.1.1:443".parse().unwrap())
+        );
+        assert_eq!(
+            probe("dns:9.9.9.9").unwrap(),
+            ProbeKind::Dns("9.9.9.9:53".parse().unwrap())
+        );
+        assert_eq!(
+            probe("dns:[2620:fe::fe]:5353").unwrap(),
+            ProbeKind::Dns("[2620:fe::fe]:5353".parse().unwrap())
+        );
+        assert_eq!(
+            probe("http://example.com/").unwrap(),
+            ProbeKind::Http(String::from("http://example.com/"))
+        );
+
+        for invalid in [
+            "1.1.1.1",
+            "icmp:",
+            "tcp:1.1.1.1",
+            "udp:1.1.1.1:53",
+            "https://a",
+        ] {
+            assert!(probe(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn quorums() {
+        let QuorumArg(up, down, window) = "2/3/10s".parse().unwrap();
+        assert_eq!((up, down, window), (2, 3, Some(Duration::from_secs(10))));
+        let QuorumArg(up, down, window) = "1/1".parse().unwrap();
+        assert_eq!((up, down, window), (1, 1, None));
+
+        for invalid in ["", "1", "0/1", "1/0", "1/1/1", "1/1/1s/1", "a/1"] {
+            assert!(invalid.parse::<QuorumArg>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn config_file() {
+        let file: Options = toml::from_str(
+            r#"
+            interface = "eth0"
+            rxtx_threshold = "2s"
+            icmp_targets = []
+            probes = ["icmp:1.1.1.1", "dns:9.9.9.9"]
+            quorum = "1/2"
+            probe_driven = true
+            "#,
+        )
+        .unwrap();
+        let config = file.config().unwrap();
+        assert_eq!(config.rxtx_threshold, Duration::from_secs(2));
+        assert_eq!(config.icmp_targets, None);
+        assert_eq!(config.probes.len(), 2);
+        assert!(config.probe_driven);
+        // The window defaults to the threshold.
+        assert_eq!(
+            config.quorum.map(|q| (q.up, q.down, q.window)),
+            Some((1, 2, Duration::from_secs(2)))
+        );
+
+        assert!(toml::from_str::<Options>(r#"probes = ["icmp"]"#).is_err());
+        assert!(toml::from_str::<Options>(r#"rxtx_treshold = "1s""#).is_err());
+    }
+
+    #[test]
+    fn cli_takes_precedence() {
+        let file: Options =
+            toml::from_str("interface = \"eth0\"\nicmp_interval = \"5s\"\nlocalize = true")
+                .unwrap();
+        let Cli { watch, .. } = Cli::try_parse_from(["n-rt-onl", "--icmp-interval=2s"]).unwrap();
+
+        let (interface, _, config) = settings(watch, file).unwrap();
+        assert_eq!(interface, "eth0");
+        assert_eq!(config.icmp_interval, Some(Duration::from_secs(2)));
+        assert!(config.localize);
+    }
+
+    #[test]
+    fn invalid_options() {
+        let config = |toml: &str| toml::from_str::<Options>(toml).unwrap().config();
+        assert!(config("rxtx_threshold = \"0s\"").is_err());
+        assert!(config("captive_portal_status = 200").is_err());
+        assert!(config("captive_portal = \"https://example.com\"").is_err());
+        assert!(config("captive_portal = \"default\"").is_ok());
+    }
+
+    #[test]
+    fn days_back() {
+        let to = SystemTime::UNIX_EPOCH + Duration::from_secs(10 * 86400);
+        assert_eq!(
+            days_before(to, 3).unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(7 * 86400)
+        );
+        assert!(days_before(to, u64::MAX).is_err());
+        assert!(days_before(to, u64::MAX / 86400).is_err());
+    }
+}
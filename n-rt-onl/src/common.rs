@@ -1,18 +1,682 @@
-use fastping_rs::Pinger;
+use std::{
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
 
-pub(crate) fn start_pinger(targets: Vec<String>, icmp_interval: Option<u64>) {
+use pnet::datalink::{self, NetworkInterface};
+use tokio::sync::{broadcast, mpsc::Sender, watch};
+
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::{
+    history, localize, netns,
+    probe::{self, scheduler::Outcomes},
+    suspend::SuspendDetector,
+    Cause, Config, Event, GatewayStatus, History, LinkStatus, ProbeKind, ProbeResult, ProbeTarget,
+    Quorum, Signal, State,
+};
+
+/// What the analysis watches besides the RX/TX timestamps.
+pub(crate) struct Monitors {
+    /// Name of the monitored interface.
+    pub iface: watch::Receiver<String>,
+    pub link: Option<watch::Receiver<LinkStatus>>,
+    pub gateway: Option<watch::Receiver<Option<GatewayStatus>>>,
+    pub probes: Option<Outcomes>,
+    /// Last packet seen leaving the interface, which unlike the TX
+    /// timestamp isn't reset by the RX. None if it can't be read.
+    pub egress: Option<Box<dyn Fn() -> Option<u64> + Send>>,
+    /// Whether the connectivity check is intercepted.
+    pub portal: Option<watch::Receiver<bool>>,
+    /// Whether the DNS traffic is degraded.
+    pub dns: Option<watch::Receiver<bool>>,
+    /// Whether the TCP loss is too high.
+    pub tcp: Option<watch::Receiver<bool>>,
+    /// Where the transitions are recorded.
+    pub history: Option<History>,
+    /// Where the state and the timestamps are exported.
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<metrics::Registered>,
+}
+
+/// Start probing the `probes` and `icmp_targets` of the config, if any,
+/// through the interface `iface` if bind_probes is set.
+/// `now` gives the time in the clock of the RX/TX timestamps.
+pub(crate) fn start_probes(
+    iface: &watch::Receiver<String>,
+    now: fn() -> u64,
+    config: &Config,
+    results_tx: broadcast::Sender<ProbeResult>,
+) -> Option<Outcomes> {
+    let interval = config.icmp_interval.unwrap_or(Duration::from_secs(1));
+    let mut targets = config.probes.clone();
+    for target in config.icmp_targets.iter().flatten() {
+        match target.parse() {
+            Ok(ip) => targets.push(ProbeTarget {
+                interval,
+                ..ProbeTarget::new(ProbeKind::Icmp(ip))
+            }),
+            Err(e) => warn!("invalid icmp target {}: {}", target, e),
+        }
+    }
+    if targets.is_empty() {
+        if config.probe_driven {
+            warn!("probe_driven is set but there's nothing to probe");
+        }
+        return None;
+    }
+    if let Some(quorum) = &config.quorum {
+        let total: u32 = targets.iter().map(|t| t.weight).sum();
+        if quorum.up > total || quorum.down > total {
+            warn!(
+                "the targets only weigh {}, the quorum can't be reached",
+                total
+            );
+        }
+    }
+
+    let iface = config.bind_probes.then(|| iface.clone());
+    Some(probe::scheduler::start_probes(
+        targets, iface, now, results_tx,
+    ))
+}
+
+/// Find the network interface with the provided name.
+pub(crate) fn find_interface(name: &str) -> Option<NetworkInterface> {
+    netns::run(datalink::interfaces)
+        .into_iter()
+        .find(|iface: &NetworkInterface| iface.name == name)
+}
+
+/// Release of the running kernel, e.g. 6.1.0-18-amd64.
+#[cfg(target_os = "linux")]
+pub(crate) fn kernel_release() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+
+    Some(
+        unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Major and minor version of the running kernel.
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+pub(crate) fn kernel_version() -> Option<(u32, u32)> {
+    let release = kernel_release()?;
+    let mut version = release.split(|c: char| !c.is_ascii_digit());
+
+    Some((version.next()?.parse().ok()?, version.next()?.parse().ok()?))
+}
+
+/// Convert a Duration into the unit used by the RX/TX timestamps
+/// of every backends (ns from a monotonic clock).
+pub(crate) fn to_ns(d: Duration) -> u64 {
+    u64::try_from(d.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Compute the state following `current` given the last RX/TX timestamps (in ns).
+/// Returns None if the state should not change.
+pub(crate) fn next_state(
+    current: &State,
+    rx_ns: u64,
+    tx_ns: u64,
+    threshold: Duration,
+) -> Option<State> {
+    let abs_diff = rx_ns.abs_diff(tx_ns);
+    let threshold = to_ns(threshold);
+
+    match current {
+        State::Up | State::Ukn if abs_diff > threshold => Some(State::Down),
+        State::Down if abs_diff < threshold => Some(State::Up),
+        // If the state is still Ukn, this means we're Up.
+        State::Ukn => Some(State::Up),
+        _ => None,
+    }
+}
+
+/// Start watching the carrier of the interface, if enabled and supported.
+pub(crate) fn start_link_monitor(
+    iface: &watch::Receiver<String>,
+    config: &Config,
+) -> Result<Option<watch::Receiver<LinkStatus>>, anyhow::Error> {
+    #[cfg(target_os = "linux")]
+    if config.link_monitor {
+        return Ok(Some(crate::netlink::watch_link(iface.clone())?));
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = (iface, config);
+
+    Ok(None)
+}
+
+/// Start probing the gateway of the interface, if enabled.
+pub(crate) fn start_gateway_monitor(
+    iface: &watch::Receiver<String>,
+    link: &Option<watch::Receiver<LinkStatus>>,
+    config: &Config,
+) -> Option<watch::Receiver<Option<GatewayStatus>>> {
+    let interval = config.gateway_probe_interval?;
+    if config.gateway.is_none() && link.is_none() {
+        warn!("the gateway to probe is unknown without link_monitor");
+    }
+
+    Some(probe::gateway::watch_gateway(
+        iface.clone(),
+        link.clone(),
+        config.gateway,
+        interval,
+    ))
+}
+
+/// Start the captive portal check, if enabled.
+pub(crate) fn start_portal_monitor(
+    iface: &watch::Receiver<String>,
+    config: &Config,
+) -> Option<watch::Receiver<bool>> {
+    let check = config.captive_portal.clone()?;
+    let iface = config.bind_probes.then(|| iface.clone());
+
+    Some(probe::portal::watch_portal(iface, check))
+}
+
+/// Find the interface the default route goes through.
+pub(crate) fn default_route_interface() -> Option<NetworkInterface> {
+    // Connecting an UDP socket doesn't send anything but makes the system pick
+    // the source address, and so the interface, from the routing table.
+    [
+        ("0.0.0.0:0", "1.1.1.1:53"),
+        ("[::]:0", "[2606:4700:4700::1111]:53"),
+    ]
+    .into_iter()
+    .find_map(|(local, remote)| {
+        let socket = netns::run(|| UdpSocket::bind(local)).ok()?;
+        socket.connect(remote).ok()?;
+        let addr = socket.local_addr().ok()?.ip();
+
+        netns::run(datalink::interfaces)
+            .into_iter()
+            .find(|iface| iface.ips.iter().any(|ip| ip.ip() == addr))
+    })
+}
+
+/// Return the name of the monitored interface, which follows
+/// the default route if `follow_default_route` is set.
+pub(crate) fn start_iface_follower(iface_name: &str, config: &Config) -> watch::Receiver<String> {
+    let (iface_tx, iface_rx) = watch::channel(iface_name.to_owned());
+    if !config.follow_default_route {
+        return iface_rx;
+    }
+
+    let interval = config.rxtx_threshold;
     tokio::spawn(async move {
-        let (pinger, results) = match Pinger::new(icmp_interval, Some(32)) {
-            Ok((pinger, results)) => (pinger, results),
-            Err(e) => panic!("Error creating pinger: {}", e),
-        };
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let Some(iface) = default_route_interface() else {
+                continue;
+            };
+            iface_tx.send_if_modified(|current| {
+                if *current == iface.name {
+                    return false;
+                }
+                info!("Default route moved from {} to {}", current, iface.name);
+                *current = iface.name;
+                true
+            });
+        }
+    });
+
+    iface_rx
+}
+
+/// Wait for a change of the watched value, forever if we don't have any.
+async fn changed<T>(rx: &mut Option<watch::Receiver<T>>) {
+    if let Some(rx) = rx {
+        if rx.changed().await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Whether the probes leave through the interface: some packet must have
+/// left it after a successful probe was sent, whatever came in meanwhile.
+#[derive(Debug)]
+struct ProbesSeen {
+    /// Last successful probe sent, and when we noticed it.
+    sent: Option<(u64, Instant)>,
+    seen: bool,
+}
+
+impl Default for ProbesSeen {
+    fn default() -> Self {
+        ProbesSeen {
+            sent: None,
+            seen: true,
+        }
+    }
+}
+
+impl ProbesSeen {
+    /// Check, `wait` after it was noticed, that the probe sent at `last_sent`
+    /// was followed by a packet leaving at `egress`. Returns whether the
+    /// probes are seen when it changes.
+    fn check(&mut self, last_sent: u64, egress: u64, wait: Duration, now: Instant) -> Option<bool> {
+        let mut changed = None;
+        if let Some((sent, at)) = self.sent {
+            if now.duration_since(at) >= wait {
+                let seen = egress >= sent;
+                if seen != self.seen {
+                    self.seen = seen;
+                    changed = Some(seen);
+                }
+                self.sent = None;
+            }
+        }
+        if self.sent.is_none() {
+            self.sent = Some((last_sent, now));
+        }
+
+        changed
+    }
+}
 
-        for t in targets {
-            pinger.add_ipaddr(&t);
+/// Run the analysis loop shared by all the backends, until the receiver
+/// of the events is dropped. `timestamps` returns the last (RX, TX)
+/// timestamps in ns, None if the interface is missing, or an error
+/// if they can't be read.
+pub(crate) async fn analyse<F>(
+    config: Config,
+    event_tx: Sender<Event>,
+    monitors: Monitors,
+    timestamps: F,
+) where
+    F: Fn() -> Result<Option<(u64, u64)>, anyhow::Error>,
+{
+    let Monitors {
+        iface,
+        mut link,
+        mut gateway,
+        probes,
+        egress,
+        mut portal,
+        mut dns,
+        mut tcp,
+        mut history,
+        #[cfg(feature = "metrics")]
+        metrics,
+    } = monitors;
+    // Need some inner state to know if we're in an "outage" or not
+    let mut current = State::Ukn;
+    _ = event_tx.send(Event::new(State::Ukn, None)).await;
+
+    // The outage going on before a restart, carried on by the first
+    // known state if it's still Down.
+    let mut recovered = None;
+    if let Some(history) = &mut history {
+        let ifname = iface.borrow().clone();
+        recovered = history
+            .ongoing(&ifname)
+            .filter(|period| period.state == State::Down);
+        if let Some(period) = &recovered {
+            info!(
+                "iface({}) was Down for {:?} before the restart, the outage goes on if it still is",
+                period.interface,
+                period.duration()
+            );
+        }
+        if let Err(e) = history.start(&ifname) {
+            error!("cannot record the start of the monitoring: {}", e);
         }
+    }
 
-        pinger.run_pinger();
+    // Delay the start of the analysis by rxtx_threshold.
+    // At first we don't have any stats, so no need to check anything
+    tokio::select! {
+        _ = tokio::time::sleep(config.rxtx_threshold) => {}
+        _ = event_tx.closed() => return,
+    }
 
-        while results.recv().is_ok() {}
+    let mut suspend = SuspendDetector::new();
+    let mut next_tick = Duration::ZERO;
+    let mut had_default_route = false;
+    let mut last_index = None;
+    let mut last_gateway = None;
+    let mut dns_degraded = false;
+    let quorum = config.quorum.clone().unwrap_or(Quorum {
+        up: 1,
+        down: 1,
+        window: config.rxtx_threshold,
     });
+    let mut probes_seen = ProbesSeen::default();
+    loop {
+        // Wake up either for the periodic analysis or as soon as the link
+        // or the gateway changes.
+        tokio::select! {
+            _ = tokio::time::sleep(next_tick) => {}
+            _ = changed(&mut link) => {}
+            _ = changed(&mut gateway) => {}
+            _ = changed(&mut portal) => {}
+            _ = changed(&mut dns) => {}
+            _ = changed(&mut tcp) => {}
+            _ = event_tx.closed() => return,
+        }
+
+        let start_overall = Instant::now();
+        // Perform three times more analysis than the rxtx_threshold.
+        // This is to avoid bad race condition where it would take
+        // more time than needed to detect outages.
+        next_tick = config.rxtx_threshold / 3;
+
+        // After a resume the stored timestamps are stale, reset the state
+        // and give the link the time to come back before analysing again.
+        if let Some(suspended) = suspend.check(config.rxtx_threshold) {
+            info!("Resumed after {:?} of suspend, resetting state", suspended);
+            if current != State::Ukn {
+                _ = event_tx.send(Event::new(State::Ukn, None)).await;
+                current = State::Ukn;
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = &metrics {
+                    metrics.state(&current);
+                }
+            }
+            next_tick = config.rxtx_threshold;
+            continue;
+        }
+
+        let status = link.as_ref().map(|l| l.borrow().clone());
+        let pkts = match timestamps() {
+            Ok(pkts) => pkts,
+            // E.g. the maps can't be read without CAP_BPF, the state would be stale.
+            Err(e) => {
+                if current != State::Error {
+                    error!("cannot read the RX/TX timestamps: {}", e);
+                    current = State::Error;
+                    #[cfg(feature = "metrics")]
+                    if let Some(metrics) = &metrics {
+                        metrics.state(&current);
+                    }
+                    _ = event_tx.send(Event::new(State::Error, None)).await;
+                }
+                continue;
+            }
+        };
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &metrics {
+            metrics.timestamps(pkts);
+        }
+        let missing = pkts.is_none() || status.as_ref().is_some_and(|s| s.index.is_none());
+        let index = status.as_ref().and_then(|s| s.index);
+        if index != last_index {
+            // A re-created (or another) interface starts from scratch.
+            had_default_route = false;
+            last_index = index;
+        }
+
+        // Without carrier or default route we're Down whatever the packets say,
+        // and we can't go back Up until they're there again. The route is only
+        // taken into account once we've seen one on the interface.
+        had_default_route |= status.as_ref().is_some_and(|s| s.default_route);
+        let down_cause = match &status {
+            Some(s) if !s.carrier => Some(Cause::LinkDown),
+            Some(s) if had_default_route && !s.default_route => Some(Cause::RouteLost),
+            _ => None,
+        };
+
+        if let (Some(probes), Some(egress)) = (&probes, egress.as_ref().and_then(|e| e())) {
+            let wait = config.rxtx_threshold / 3;
+            match probes_seen.check(probes.last_sent(), egress, wait, Instant::now()) {
+                Some(true) => info!(
+                    "The probes are seen leaving iface({}) again",
+                    *iface.borrow()
+                ),
+                Some(false) => warn!("The probes aren't seen leaving iface({})", *iface.borrow()),
+                None => {}
+            }
+        }
+
+        // Behind a captive portal or with a lossy link we're Up
+        // as far as the packets are concerned.
+        let base = match current {
+            State::CaptivePortal | State::Degraded => State::Up,
+            // Start over once the timestamps can be read again.
+            State::Error => State::Ukn,
+            _ => current.clone(),
+        };
+        let next = if missing {
+            (current != State::Missing).then_some(Event::new(State::Missing, None))
+        } else if current == State::Missing {
+            // The interface is back, give it the time to settle as after a resume.
+            next_tick = config.rxtx_threshold;
+            Some(Event::new(State::Ukn, None))
+        } else if let Some(cause) = down_cause {
+            (current != State::Down).then_some(Event::new(State::Down, Some(cause)))
+        } else if let (true, Some(probes)) = (config.probe_driven, &probes) {
+            match (&base, probes.quorum(&quorum)) {
+                (State::Down | State::Ukn, (true, _)) => Some(Event::new(State::Up, None)),
+                (State::Up | State::Ukn, (false, true)) => {
+                    Some(Event::new(State::Down, Some(Cause::ProbesFailed)))
+                }
+                _ => None,
+            }
+        } else {
+            pkts.and_then(|(rx_pkt, tx_pkt)| {
+                next_state(&base, rx_pkt, tx_pkt, config.rxtx_threshold)
+            })
+            .map(|state| {
+                let cause = (state == State::Down).then_some(Cause::RxTxGap);
+                Event::new(state, cause)
+            })
+        };
+
+        let up = if portal.as_ref().is_some_and(|p| *p.borrow()) {
+            State::CaptivePortal
+        } else if tcp.as_ref().is_some_and(|t| *t.borrow()) {
+            State::Degraded
+        } else {
+            State::Up
+        };
+        let next = match next {
+            Some(event) if event.state == State::Up => Some(Event::new(up, None)),
+            None if base == State::Up && current != up => Some(Event::new(up, None)),
+            next => next,
+        };
+
+        let gateway_status = gateway.as_ref().and_then(|g| g.borrow().clone());
+        let gateway_key = gateway_status.as_ref().map(|g| (g.address, g.reachable));
+        let mut signal = (gateway_key != last_gateway).then_some(Signal::Gateway);
+        last_gateway = gateway_key;
+        // One signal per event, the DNS one waits for the next tick if needed.
+        let degraded = dns.as_ref().is_some_and(|d| *d.borrow());
+        if signal.is_none() && degraded != dns_degraded {
+            signal = Some(if degraded {
+                Signal::DnsDegraded
+            } else {
+                Signal::DnsRecovered
+            });
+            dns_degraded = degraded;
+        }
+        // Report the gateway and DNS changes even if the state doesn't change.
+        let next = match (next, signal) {
+            (None, Some(signal)) => Some(Event {
+                signal: Some(signal),
+                ..Event::new(current.clone(), None)
+            }),
+            (Some(event), signal) => Some(Event { signal, ..event }),
+            (None, None) => None,
+        };
+
+        if let Some(mut event) = next {
+            if let (true, Some(cause)) = (config.localize, &event.cause) {
+                let ifname = iface.borrow().clone();
+                event.scope = localize::localize(&ifname, cause, status.as_ref(), &config).await;
+            }
+            event.link = status;
+            event.gateway = gateway_status;
+            if history::known(&event.state) {
+                if let Some(period) = recovered.take() {
+                    event.since = (event.state == State::Down).then_some(period.start);
+                }
+            }
+            info!("State now {:?}", event);
+            if let Some(history) = &mut history {
+                let ifname = iface.borrow().clone();
+                if let Err(e) = history.record(&ifname, &event) {
+                    error!("cannot record the transition: {}", e);
+                }
+            }
+            current = event.state.clone();
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &metrics {
+                metrics.state(&current);
+            }
+            _ = event_tx.send(event).await;
+        }
+
+        next_tick = next_tick.saturating_sub(start_overall.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: Duration = Duration::from_millis(1500);
+
+    /// Timestamps (RX, TX) in ns, TX `gap` after RX, as both backends
+    /// get them: from a clock converted with to_ns.
+    fn pkts(gap: Duration) -> (u64, u64) {
+        let rx = to_ns(Duration::from_secs(3600));
+        (rx, rx + to_ns(gap))
+    }
+
+    fn next(current: State, gap: Duration) -> Option<State> {
+        let (rx, tx) = pkts(gap);
+        next_state(&current, rx, tx, THRESHOLD)
+    }
+
+    #[test]
+    fn to_ns_is_in_nanoseconds() {
+        assert_eq!(to_ns(THRESHOLD), 1_500_000_000);
+        assert_eq!(to_ns(Duration::from_nanos(1)), 1);
+        assert_eq!(to_ns(Duration::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn at_the_threshold_nothing_changes() {
+        assert_eq!(next(State::Up, THRESHOLD), None);
+        assert_eq!(next(State::Down, THRESHOLD), None);
+    }
+
+    #[test]
+    fn trips_one_ns_over_the_threshold() {
+        let gap = THRESHOLD + Duration::from_nanos(1);
+        assert_eq!(next(State::Up, gap), Some(State::Down));
+        assert_eq!(next(State::Ukn, gap), Some(State::Down));
+        assert_eq!(next(State::Down, gap), None);
+    }
+
+    #[test]
+    fn recovers_one_ns_under_the_threshold() {
+        assert_eq!(
+            next(State::Down, THRESHOLD - Duration::from_nanos(1)),
+            Some(State::Up)
+        );
+    }
+
+    #[test]
+    fn equal_timestamps_are_up() {
+        assert_eq!(next(State::Ukn, Duration::ZERO), Some(State::Up));
+        assert_eq!(next(State::Down, Duration::ZERO), Some(State::Up));
+        assert_eq!(next(State::Up, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn the_gap_goes_both_ways() {
+        let (rx, tx) = pkts(THRESHOLD * 2);
+        assert_eq!(next_state(&State::Up, tx, rx, THRESHOLD), Some(State::Down));
+    }
+
+    #[test]
+    fn probes_leaving_another_interface_arent_seen() {
+        let wait = THRESHOLD / 3;
+        let start = Instant::now();
+        let mut check = ProbesSeen::default();
+        let sent = to_ns(Duration::from_secs(3600));
+
+        // The probes go out through another interface: nothing leaves the
+        // watched one after them, however much it receives.
+        let egress = sent - 1;
+        assert_eq!(check.check(sent, egress, wait, start), None);
+        assert_eq!(check.check(sent, egress, wait, start + wait / 2), None);
+        assert_eq!(check.check(sent, egress, wait, start + wait), Some(false));
+        // Only reported when it changes.
+        assert_eq!(check.check(sent, egress, wait, start + wait * 2), None);
+
+        // Back through the watched interface.
+        let later = sent + to_ns(wait * 2);
+        assert_eq!(
+            check.check(later, later, wait, start + wait * 3),
+            Some(true)
+        );
+    }
+
+    /// A backend, and the clock it timestamps the packets with.
+    type Clock = (&'static str, fn() -> u64);
+
+    fn clocks() -> Vec<Clock> {
+        vec![
+            // bpf_ktime_get_ns, i.e. CLOCK_MONOTONIC.
+            #[cfg(target_os = "linux")]
+            ("ebpf", || {
+                to_ns(crate::suspend::clock_gettime(libc::CLOCK_MONOTONIC))
+            }),
+            #[cfg(any(feature = "userspace", not(target_os = "linux")))]
+            ("userspace", crate::other::get_now),
+        ]
+    }
+
+    #[test]
+    fn the_clocks_of_the_backends_are_in_ns() {
+        let pause = Duration::from_millis(150);
+        for (backend, now) in clocks() {
+            let start = Instant::now();
+            let before = now();
+            std::thread::sleep(pause);
+            let after = now();
+            let elapsed = start.elapsed();
+
+            let measured = after - before;
+            assert!(measured >= to_ns(pause), "{}: {}", backend, measured);
+            assert!(measured <= to_ns(elapsed), "{}: {}", backend, measured);
+        }
+    }
+
+    #[test]
+    fn the_backends_trip_after_the_threshold() {
+        let threshold = Duration::from_millis(100);
+        for (backend, now) in clocks() {
+            let rx = now();
+            assert_eq!(
+                next_state(&State::Up, rx, now(), threshold),
+                None,
+                "{}",
+                backend
+            );
+
+            std::thread::sleep(threshold + Duration::from_millis(50));
+            let tx = now();
+            assert_eq!(
+                next_state(&State::Up, rx, tx, threshold),
+                Some(State::Down),
+                "{}",
+                backend
+            );
+        }
+    }
 }
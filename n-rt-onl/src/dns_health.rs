@@ -0,0 +1,122 @@
+//! Passive DNS health: the backends match the queries seen in the traffic
+//! with their responses, the statistics and the verdict are computed here.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+use crate::{common, DnsMonitor, ResolverStats};
+
+/// Query waiting for its response, same layout as
+/// the keys of DNS_PENDING in the eBPF program.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Query {
+    pub resolver: u32,
+    /// Source port of the query.
+    pub port: u16,
+    pub id: u16,
+}
+
+/// Counters of a resolver, same layout as
+/// the values of DNS_STATS in the eBPF program.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Counters {
+    pub queries: u64,
+    pub responses: u64,
+    pub servfail: u64,
+    pub nxdomain: u64,
+    /// Sum of the time it took to get the responses.
+    pub latency_ns: u64,
+}
+
+/// What the backend has seen.
+pub(crate) struct Snapshot {
+    pub counters: Vec<(Ipv4Addr, Counters)>,
+    /// Queries of each resolver which timed out. Counted by the backends
+    /// so that they cover the same time as the other counters.
+    pub timeouts: Vec<(Ipv4Addr, u64)>,
+}
+
+/// Take a snapshot of what the backend has seen every second, `collect` being
+/// given the time (from `now`, the clock of the backend) before which the
+/// unanswered queries timed out. The statistics are published on `stats_tx`,
+/// the receivers of the returned channel are notified when the DNS becomes
+/// degraded or recovers.
+pub(crate) fn watch_dns<F>(
+    monitor: DnsMonitor,
+    now: fn() -> u64,
+    mut collect: F,
+    stats_tx: watch::Sender<Vec<ResolverStats>>,
+) -> watch::Receiver<bool>
+where
+    F: FnMut(u64) -> Snapshot + Send + 'static,
+{
+    let (degraded_tx, degraded_rx) = watch::channel(false);
+
+    tokio::spawn(async move {
+        // (when, failures, queries) over the window.
+        let mut totals: VecDeque<(Instant, u64, u64)> = VecDeque::new();
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+
+            let snapshot = collect(now().saturating_sub(common::to_ns(monitor.timeout)));
+            let timeouts: BTreeMap<Ipv4Addr, u64> = snapshot.timeouts.into_iter().collect();
+
+            let mut stats: Vec<ResolverStats> = snapshot
+                .counters
+                .into_iter()
+                .map(|(resolver, c)| ResolverStats {
+                    resolver: IpAddr::V4(resolver),
+                    queries: c.queries,
+                    responses: c.responses,
+                    timeouts: timeouts.get(&resolver).copied().unwrap_or_default(),
+                    servfail: c.servfail,
+                    nxdomain: c.nxdomain,
+                    latency: (c.responses > 0)
+                        .then(|| Duration::from_nanos(c.latency_ns / c.responses)),
+                })
+                .collect();
+            stats.sort_by_key(|s| s.resolver);
+
+            let failures = stats.iter().map(|s| s.timeouts + s.servfail).sum();
+            let queries = stats.iter().map(|s| s.queries).sum();
+            stats_tx.send_replace(stats);
+
+            totals.push_back((Instant::now(), failures, queries));
+            while totals
+                .front()
+                .is_some_and(|(at, _, _)| at.elapsed() > monitor.window)
+            {
+                totals.pop_front();
+            }
+            let (_, first_failures, first_queries) = totals[0];
+            let failures = failures.saturating_sub(first_failures);
+            let queries = queries.saturating_sub(first_queries);
+
+            let degraded = queries >= monitor.min_queries
+                && failures as f32 >= monitor.max_failures * queries as f32;
+            degraded_tx.send_if_modified(|current| {
+                if *current == degraded {
+                    return false;
+                }
+                if degraded {
+                    warn!("DNS degraded: {} of {} queries failed", failures, queries);
+                } else {
+                    info!("DNS recovered");
+                }
+                *current = degraded;
+                true
+            });
+        }
+    });
+
+    degraded_rx
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::os::unix::{
+    fs::MetadataExt,
+    io::{AsFd, AsRawFd},
+};
+use std::path::{Component, Path, PathBuf};
+
+use aya::maps::{HashMap, MapError};
+use aya::programs::{CgroupSkb, CgroupSkbAttachType};
+use aya::Bpf;
+
+const PROGRAMS: [(&str, CgroupSkbAttachType); 2] = [
+    ("n_rt_onl_cgroup_egress", CgroupSkbAttachType::Egress),
+    ("n_rt_onl_cgroup_ingress", CgroupSkbAttachType::Ingress),
+];
+
+/// Load the cgroup_skb programs and attach them to the root of the
+/// cgroup v2 hierarchy, from where they see the traffic of every cgroup.
+pub(super) fn attach(bpf: &mut Bpf, root: &Path) -> Result<(), anyhow::Error> {
+    // The links hold their own reference to the cgroup, closed once attached.
+    let cgroup = File::open(root)?;
+    for (name, attach_type) in PROGRAMS {
+        let program: &mut CgroupSkb = bpf.program_mut(name).unwrap().try_into()?;
+        program.load()?;
+        program.attach(cgroup.as_fd().as_raw_fd(), attach_type)?;
+    }
+
+    Ok(())
+}
+
+/// Tell the cgroup_skb programs which interface is monitored.
+pub(super) fn set_interface(bpf: &mut Bpf, index: u32) -> Result<(), anyhow::Error> {
+    let mut iface = HashMap::<_, u8, u32>::try_from(bpf.map_mut("IFACE_INDEX").unwrap())?;
+    iface.insert(0, index, 0)?;
+
+    Ok(())
+}
+
+/// Path of the cgroup `path` relative to the `root` of the hierarchy,
+/// with or without the leading / (as in /proc/<pid>/cgroup).
+pub(super) fn path(root: &Path, path: &Path) -> Result<PathBuf, anyhow::Error> {
+    let relative = path.strip_prefix("/").unwrap_or(path);
+    if relative.components().any(|c| c == Component::ParentDir) {
+        anyhow::bail!("{} is out of the cgroup hierarchy", path.display());
+    }
+
+    Ok(root.join(relative))
+}
+
+/// ID of the cgroup (v2), the inode of its directory.
+pub(crate) fn cgroup_id(path: &Path) -> Result<u64, anyhow::Error> {
+    Ok(std::fs::metadata(path)?.ino())
+}
+
+/// Last RX/TX timestamps of the cgroup, 0 before its first packet.
+pub(super) fn timestamps(bpf: &Bpf, id: u64) -> Result<(u64, u64), anyhow::Error> {
+    let timestamps = HashMap::<_, u64, [u64; 2]>::try_from(bpf.map("CGROUP_TIMESTAMP").unwrap())?;
+
+    match timestamps.get(&id, 0) {
+        Ok([rx, tx]) => Ok((rx, tx)),
+        Err(MapError::KeyNotFound) => Ok((0, 0)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_under_the_root() {
+        let root = Path::new("/sys/fs/cgroup");
+        let unit = PathBuf::from("/sys/fs/cgroup/system.slice/docker.service");
+        assert_eq!(
+            path(root, Path::new("system.slice/docker.service")).unwrap(),
+            unit
+        );
+        assert_eq!(
+            path(root, Path::new("/system.slice/docker.service")).unwrap(),
+            unit
+        );
+        assert_eq!(path(root, Path::new("/")).unwrap(), root);
+        assert!(path(root, Path::new("../../etc")).is_err());
+    }
+}
@@ -1,15 +1,387 @@
-use aya::maps::HashMap;
-use aya::programs::{tc, SchedClassifier, TcAttachType};
+use std::fs::File;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use aya::maps::{HashMap, MapError};
+use aya::programs::{
+    tc::{self, SchedClassifierLinkId, TcOptions},
+    xdp::XdpLinkId,
+    ProgramFd, SchedClassifier, TcAttachType,
+};
+use aya::Bpf;
 use aya_log::BpfLogger;
-use std::time::Duration;
-use tokio::sync::mpsc::Receiver;
+use pnet::datalink::NetworkInterface;
+use tokio::sync::mpsc::{self, Receiver};
+
+use super::{cgroup, pin, tcp, tcx, xdp};
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::{
+    common,
+    dns_health::{self, Counters, Query, Snapshot},
+    history, netlink, netns, privileges, suspend, Config, Event, Onl, TcAttach, TcMode,
+};
+
+// Same layout as in the eBPF program.
+unsafe impl aya::Pod for Query {}
+unsafe impl aya::Pod for Counters {}
+
+const PROGRAMS: [(&str, TcAttachType); 2] = [
+    ("n_rt_onl_ebpf_egress", TcAttachType::Egress),
+    ("n_rt_onl_ebpf_ingress", TcAttachType::Ingress),
+];
+
+/// A classifier attached to an interface.
+enum Link {
+    /// Filter of the clsact qdisc.
+    Tc(&'static str, SchedClassifierLinkId),
+    /// TCX link, detached once closed and unpinned.
+    Tcx(File, Option<PathBuf>),
+    /// XDP program replacing the ingress classifier.
+    Xdp(XdpLinkId),
+}
+
+/// Classifiers attached to an interface.
+struct Attached {
+    index: u32,
+    links: Vec<Link>,
+    /// The clsact qdisc was added by us, and is removed once unused.
+    clsact: bool,
+}
+
+/// Detach the classifiers once the monitor stops: the receiver of the events
+/// is dropped, or the runtime shuts down.
+struct Detach {
+    bpf: Arc<Mutex<Bpf>>,
+    attached: Arc<Mutex<Option<Attached>>>,
+}
+
+impl Drop for Detach {
+    fn drop(&mut self) {
+        let (Ok(mut bpf), Ok(mut attached)) = (self.bpf.lock(), self.attached.lock()) else {
+            return;
+        };
+        if let Some(attached) = attached.take() {
+            detach(&mut bpf, attached);
+        }
+    }
+}
+
+/// Same clock as bpf_ktime_get_ns.
+fn monotonic_ns() -> u64 {
+    common::to_ns(suspend::clock_gettime(libc::CLOCK_MONOTONIC))
+}
+
+/// Value of a timestamp or counter, 0 until the programs set it.
+pub(super) fn value(result: Result<u64, MapError>) -> Result<u64, MapError> {
+    match result {
+        Err(MapError::KeyNotFound) => Ok(0),
+        result => result,
+    }
+}
+
+/// Packets seen by the programs, RX and TX.
+#[cfg(feature = "metrics")]
+fn packets(bpf: &Bpf) -> Option<(u64, u64)> {
+    // Missing from the maps pinned by an older version.
+    let count = HashMap::<_, u8, u64>::try_from(bpf.map("PKT_COUNT")?).ok()?;
+
+    Some((
+        count.get(&0, 0).unwrap_or_default(),
+        count.get(&1, 0).unwrap_or_default(),
+    ))
+}
+
+/// Load the eBPF object, its maps being pinned under the directory of the
+/// interface with Config::pin. None for the observers, which only open the
+/// maps pinned by another process.
+pub(crate) fn load(ifname: &str, config: &Config) -> Result<Option<Bpf>, anyhow::Error> {
+    // The maps are found by the name of the interface given here.
+    if (config.pin || config.observer) && config.follow_default_route {
+        anyhow::bail!("pin and observer can't be used with follow_default_route");
+    }
+    if config.observer {
+        let dir = pin::dir(ifname);
+        if !dir.join("PKT_TIMESTAMP").exists() {
+            anyhow::bail!(
+                "no maps pinned in {}, is iface({}) monitored with pin?",
+                dir.display(),
+                ifname
+            );
+        }
+        return Ok(None);
+    }
+
+    let mut bpf = Bpf::load_file(&config.ebpf_prog_path)?;
+    if config.pin {
+        pin::pin(&mut bpf, ifname)?;
+    }
+
+    Ok(Some(bpf))
+}
+
+/// Remove the DNS queries sent before `before` from DNS_PENDING, counting
+/// them in DNS_TIMEOUTS, and read the counters of the resolvers.
+fn dns_snapshot(bpf: &mut Bpf, before: u64) -> Result<Snapshot, anyhow::Error> {
+    let mut pending = HashMap::<_, Query, u64>::try_from(bpf.map_mut("DNS_PENDING").unwrap())?;
+    let timed_out: Vec<Query> = pending
+        .iter()
+        .filter_map(Result::ok)
+        .filter(|(_, sent)| *sent < before)
+        .map(|(query, _)| query)
+        .collect();
+    let mut expired = Vec::with_capacity(timed_out.len());
+    for query in timed_out {
+        // The response may have arrived in the meantime.
+        if pending.remove(&query).is_ok() {
+            expired.push(query.resolver);
+        }
+    }
+
+    // Only written from here, unlike DNS_STATS.
+    let mut timeouts = HashMap::<_, u32, u64>::try_from(bpf.map_mut("DNS_TIMEOUTS").unwrap())?;
+    for resolver in expired {
+        let count = value(timeouts.get(&resolver, 0))?;
+        timeouts.insert(resolver, count + 1, 0)?;
+    }
+    let timeouts = timeouts
+        .iter()
+        .filter_map(Result::ok)
+        .map(|(resolver, count)| (Ipv4Addr::from(resolver), count))
+        .collect();
+
+    let stats = HashMap::<_, u32, Counters>::try_from(bpf.map("DNS_STATS").unwrap())?;
+    let counters = stats
+        .iter()
+        .filter_map(Result::ok)
+        .map(|(resolver, counters)| (Ipv4Addr::from(resolver), counters))
+        .collect();
+
+    Ok(Snapshot { counters, timeouts })
+}
+
+/// Attach the (loaded) classifiers to the interface.
+fn attach(
+    bpf: &mut Bpf,
+    iface: &NetworkInterface,
+    config: &Config,
+) -> Result<Attached, anyhow::Error> {
+    netns::run(|| attach_in_netns(bpf, iface, config))
+}
+
+fn attach_in_netns(
+    bpf: &mut Bpf,
+    iface: &NetworkInterface,
+    config: &Config,
+) -> Result<Attached, anyhow::Error> {
+    // Other tools (e.g. Cilium) may have their programs on the interface.
+    let filters = netlink::tc::filters(iface.index).unwrap_or_else(|e| {
+        debug!("cannot list the filters of iface({}): {}", iface.name, e);
+        Vec::new()
+    });
+    for filter in &filters {
+        info!(
+            "iface({}) has a {} filter on {} (priority {}, handle {:#x})",
+            iface.name,
+            filter.kind,
+            if filter.ingress { "ingress" } else { "egress" },
+            filter.priority,
+            filter.handle
+        );
+    }
+
+    // The XDP program takes the place of the ingress classifier.
+    let programs: Vec<_> = PROGRAMS
+        .into_iter()
+        .filter(|(_, attach_type)| config.xdp.is_none() || *attach_type == TcAttachType::Egress)
+        .collect();
+    let tc_attach = &config.tc_attach;
+    let tcx = match tc_attach.mode {
+        TcMode::Auto => tcx::supported(),
+        TcMode::Tcx => true,
+        TcMode::Netlink => false,
+    };
+    let mut attached = if tcx {
+        match attach_tcx(bpf, iface, &programs, config.pin) {
+            Ok(attached) => Ok(attached),
+            Err(e) if tc_attach.mode == TcMode::Tcx => Err(e),
+            Err(e) => {
+                warn!("cannot attach with TCX, falling back to netlink: {}", e);
+                attach_netlink(bpf, iface, &programs, tc_attach, &filters)
+            }
+        }
+    } else {
+        attach_netlink(bpf, iface, &programs, tc_attach, &filters)
+    }?;
+    if let Some(mode) = config.xdp {
+        match xdp::attach(bpf, &iface.name, mode) {
+            Ok(link_id) => attached.links.push(Link::Xdp(link_id)),
+            Err(e) => {
+                detach_in_netns(bpf, attached);
+                return Err(e);
+            }
+        }
+    }
+    cgroup::set_interface(bpf, iface.index)?;
+
+    Ok(attached)
+}
+
+fn attach_tcx(
+    bpf: &mut Bpf,
+    iface: &NetworkInterface,
+    programs: &[(&'static str, TcAttachType)],
+    pin: bool,
+) -> Result<Attached, anyhow::Error> {
+    let dir = pin::dir(&iface.name);
+    if pin {
+        std::fs::create_dir_all(&dir)?;
+    }
+
+    let mut links = Vec::with_capacity(programs.len() + 1);
+    for &(name, attach_type) in programs {
+        let program: &mut SchedClassifier = bpf.program_mut(name).unwrap().try_into()?;
+        let fd = program
+            .fd()
+            .ok_or_else(|| anyhow::anyhow!("{} isn't loaded", name))?;
+        let path = pin.then(|| dir.join(name));
+        // The link of a previous run is still attached while pinned.
+        if let Some(path) = &path {
+            tcx::unpin_stale(path);
+        }
+        let link = tcx::attach(fd, iface.index, attach_type)?;
+        if let Some(path) = &path {
+            tcx::pin(&link, path)?;
+        }
+        links.push(Link::Tcx(link, path));
+    }
+
+    Ok(Attached {
+        index: iface.index,
+        links,
+        clsact: false,
+    })
+}
+
+fn attach_netlink(
+    bpf: &mut Bpf,
+    iface: &NetworkInterface,
+    programs: &[(&'static str, TcAttachType)],
+    tc_attach: &TcAttach,
+    filters: &[netlink::tc::Filter],
+) -> Result<Attached, anyhow::Error> {
+    // The ingress qdisc takes the place of clsact, it isn't ours to replace.
+    let qdiscs = netlink::tc::qdiscs(iface.index)?;
+    let clsact = if qdiscs.iter().any(|kind| kind == "clsact") {
+        false
+    } else if qdiscs.iter().any(|kind| kind == "ingress") {
+        anyhow::bail!(
+            "iface({}) has an ingress qdisc, cannot add clsact",
+            iface.name
+        );
+    } else {
+        tc::qdisc_add_clsact(&iface.name)?;
+        true
+    };
 
-use crate::{common, Onl, State};
+    let mut links = Vec::with_capacity(programs.len() + 1);
+    for &(name, attach_type) in programs {
+        if tc_attach.priority != 0
+            && tc_attach.handle != 0
+            && filters.iter().any(|f| {
+                f.ingress == (attach_type == TcAttachType::Ingress)
+                    && f.priority == tc_attach.priority
+                    && f.handle == tc_attach.handle
+            })
+        {
+            anyhow::bail!(
+                "iface({}) already has a filter with priority {} and handle {:#x}",
+                iface.name,
+                tc_attach.priority,
+                tc_attach.handle
+            );
+        }
+        let program: &mut SchedClassifier = bpf.program_mut(name).unwrap().try_into()?;
+        // The filters outlive the process, remove those left by a previous
+        // run (e.g. it crashed) instead of running the programs twice.
+        let _ = tc::qdisc_detach_program(&iface.name, attach_type, name);
+        let options = TcOptions {
+            priority: tc_attach.priority,
+            handle: tc_attach.handle,
+        };
+        links.push(Link::Tc(
+            name,
+            program.attach_with_options(&iface.name, attach_type, options)?,
+        ));
+    }
+
+    Ok(Attached {
+        index: iface.index,
+        links,
+        clsact,
+    })
+}
+
+/// Detach the classifiers, the interface may already be gone.
+fn detach(bpf: &mut Bpf, attached: Attached) {
+    netns::run(|| detach_in_netns(bpf, attached));
+}
+
+fn detach_in_netns(bpf: &mut Bpf, attached: Attached) {
+    for link in attached.links {
+        match link {
+            Link::Tc(name, link_id) => {
+                let program: Result<&mut SchedClassifier, _> =
+                    bpf.program_mut(name).unwrap().try_into();
+                if let Ok(program) = program {
+                    let _ = program.detach(link_id);
+                }
+            }
+            Link::Tcx(link, path) => {
+                if let Some(path) = path {
+                    let _ = std::fs::remove_file(path);
+                }
+                drop(link);
+            }
+            Link::Xdp(link_id) => xdp::detach(bpf, link_id),
+        }
+    }
+    // Only remove the qdisc if nobody else uses it meanwhile.
+    if attached.clsact
+        && netlink::tc::filters(attached.index).is_ok_and(|filters| filters.is_empty())
+    {
+        let _ = netlink::tc::delete_clsact(attached.index);
+    }
+}
 
 impl Onl {
+    /// Subscribe to the events of the cgroup at `path`, relative to
+    /// Config::cgroup_root (as in /proc/<pid>/cgroup, the leading / is
+    /// optional), e.g. a Docker container or a systemd unit. They're computed
+    /// like those of the interface, from the packets of the cgroup going
+    /// through it, without the probes. Only the sockets of the cgroup itself
+    /// count, not those of its children: subscribe to the leaf cgroups.
+    pub fn subscribe_cgroup(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<Receiver<Event>, anyhow::Error> {
+        let path = cgroup::path(&self.config.cgroup_root, path.as_ref())?;
+        let id = cgroup::cgroup_id(&path)?;
+        let (event_tx, event_rx) = mpsc::channel(100);
+        self.cgroups.push((id, event_tx));
+
+        Ok(event_rx)
+    }
+
     /// Start the outage notification process.
-    /// Returning the receiver of a MPSC channel.
-    pub fn start(mut self) -> Result<Receiver<State>, anyhow::Error> {
+    /// Returning the receiver of a MPSC channel, the process
+    /// stops (and the classifiers are detached) once it's dropped.
+    pub fn start(mut self) -> Result<Receiver<Event>, anyhow::Error> {
+        let Some(mut bpf) = self.bpf.take() else {
+            return self.observe();
+        };
+
         // Bump the memlock rlimit. This is needed for older kernels that don't use the
         // new memcg based accounting, see https://lwn.net/Articles/837122/
         let rlim = libc::rlimit {
@@ -17,96 +389,300 @@ impl Onl {
             rlim_max: libc::RLIM_INFINITY,
         };
         let ret = unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlim) };
-        if ret != 0 {
-            debug!("remove limit on locked memory failed, ret is: {}", ret);
+        if ret != 0 && common::kernel_version() < Some((5, 11)) {
+            warn!(
+                "cannot remove the limit on locked memory, the maps may fail to be created: {}",
+                std::io::Error::last_os_error()
+            );
         }
 
-        if let Err(e) = BpfLogger::init(&mut self.bpf) {
+        if let Err(e) = BpfLogger::init(&mut bpf) {
             // This can happen if you remove all log statements from your eBPF program.
             warn!("failed to initialize eBPF logger: {}", e);
         }
 
-        // error adding clsact to the interface if it is already added is harmless
-        // the full cleanup can be done with 'sudo tc qdisc del dev eth0 clsact'.
-        let _ = tc::qdisc_add_clsact(&self.iface_name);
-        let program_egress: &mut SchedClassifier = self
-            .bpf
-            .program_mut("n_rt_onl_ebpf_egress")
-            .unwrap()
-            .try_into()?;
-        program_egress.load()?;
-        program_egress.attach(&self.iface_name, TcAttachType::Egress)?;
-
-        let program_ingress: &mut SchedClassifier = self
-            .bpf
-            .program_mut("n_rt_onl_ebpf_ingress")
-            .unwrap()
-            .try_into()?;
-        program_ingress.load()?;
-        program_ingress.attach(&self.iface_name, TcAttachType::Ingress)?;
-
-        // If some targets for icmp are specified, run the pinger
-        // Note: we don't care about the result, the eBPF prog will take care
-        // of that part.
-        if let Some(targets) = self.config.icmp_targets {
-            common::start_pinger(targets, self.config.icmp_interval);
-        }
-
-        tokio::spawn(async move {
-            let bpf_map = self.bpf.map_mut("PKT_TIMESTAMP").unwrap();
-            let pkt_timestamp = HashMap::<_, u8, u64>::try_from(bpf_map).unwrap();
-
-            // Need some inner state to know if we're in an "outage" or not
-            let mut current = State::Ukn;
-            _ = self.event_tx.send(State::Ukn).await;
-
-            // Delay the start of the analysis by rxtx_threshold.
-            // At first we don't have any stats, so no need to check anything
-            tokio::time::sleep(Duration::from_millis(self.config.rxtx_threshold as u64)).await;
-
-            loop {
-                let start_overall = std::time::Instant::now();
-
-                let rx_pkt = pkt_timestamp.get(&0, 0).unwrap_or_default();
-                let tx_pkt = pkt_timestamp.get(&1, 0).unwrap_or_default();
-                let abs_diff = rx_pkt.abs_diff(tx_pkt);
-
-                match current {
-                    State::Up | State::Ukn => {
-                        // If the diff is bigger than rxtx_threshold (converted to ns)
-                        if abs_diff > (self.config.rxtx_threshold * 10000000) as u64 {
-                            info!("State now DOWN");
-                            _ = self.event_tx.send(State::Down).await;
-                            current = State::Down;
+        for (name, _) in PROGRAMS {
+            let program: &mut SchedClassifier = bpf.program_mut(name).unwrap().try_into()?;
+            program.load()?;
+        }
+        if self.config.xdp.is_some() {
+            xdp::load(&mut bpf)?;
+        }
+
+        if !self.cgroups.is_empty() {
+            cgroup::attach(&mut bpf, &self.config.cgroup_root)?;
+        }
+
+        // The tracepoints may be missing, carry on without them.
+        let mut tcp_monitor = self.config.tcp_monitor.clone();
+        if let Some(monitor) = &tcp_monitor {
+            if let Err(e) = tcp::attach(&mut bpf, monitor) {
+                warn!(
+                    "cannot attach the TCP tracepoints, ignoring tcp_monitor: {}",
+                    e
+                );
+                tcp_monitor = None;
+            }
+        }
+
+        // The interface may be missing for now, in which case we'll
+        // attach once it's there.
+        let attached = match common::find_interface(&self.iface_name) {
+            Some(interface) => Some(attach(&mut bpf, &interface, &self.config)?),
+            None => None,
+        };
+        let attached = Arc::new(Mutex::new(attached));
+
+        let iface = common::start_iface_follower(&self.iface_name, &self.config);
+        let link = common::start_link_monitor(&iface, &self.config)?;
+        let gateway = common::start_gateway_monitor(&iface, &link, &self.config);
+        let portal = common::start_portal_monitor(&iface, &self.config);
+        // Probe the targets in the background, the traffic also makes sure
+        // we receive packets if the host doesn't have much to do.
+        let probes =
+            common::start_probes(&iface, monotonic_ns, &self.config, self.probe_tx.clone());
+        let bpf = Arc::new(Mutex::new(bpf));
+        let dns = self.config.dns_monitor.clone().map(|monitor| {
+            let bpf = bpf.clone();
+            dns_health::watch_dns(
+                monitor,
+                monotonic_ns,
+                move |before| {
+                    let mut bpf = bpf.lock().unwrap();
+                    dns_snapshot(&mut bpf, before).unwrap_or_else(|e| {
+                        error!("cannot read the DNS maps: {}", e);
+                        Snapshot {
+                            counters: Vec::new(),
+                            timeouts: Vec::new(),
                         }
+                    })
+                },
+                self.dns_tx.clone(),
+            )
+        });
+        let tcp = tcp_monitor.map(|monitor| {
+            tcp::watch_tcp(bpf.clone(), iface.clone(), monitor, self.tcp_tx.clone())
+        });
+
+        // The classifiers go away with the interface, re-attach them each time
+        // it's (re-)created or when we switch to another one.
+        if let Some(mut link) = link.clone() {
+            let bpf = bpf.clone();
+            let attached = attached.clone();
+            let iface = iface.clone();
+            let config = Arc::new(self.config.clone());
+            let event_tx = self.event_tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        changed = link.changed() => if changed.is_err() {
+                            break;
+                        },
+                        _ = event_tx.closed() => break,
                     }
-                    State::Down => {
-                        if abs_diff < (self.config.rxtx_threshold * 10000000) as u64 {
-                            info!("State now UP");
-                            _ = self.event_tx.send(State::Up).await;
-                            current = State::Up;
+                    let Some(index) = link.borrow().index else {
+                        continue;
+                    };
+                    // The netlink calls block, as does the lock meanwhile.
+                    let (bpf, attached, iface, config) =
+                        (bpf.clone(), attached.clone(), iface.clone(), config.clone());
+                    let reattach = tokio::task::spawn_blocking(move || {
+                        let mut bpf = bpf.lock().unwrap();
+                        let mut attached = attached.lock().unwrap();
+                        if attached.as_ref().map(|a| a.index) == Some(index) {
+                            return;
                         }
+
+                        if let Some(prev) = attached.take() {
+                            detach(&mut bpf, prev);
+                        }
+                        let Some(interface) = common::find_interface(&iface.borrow()) else {
+                            return;
+                        };
+                        info!("Attaching the classifiers to iface({})", interface.name);
+                        match attach(&mut bpf, &interface, &config) {
+                            Ok(a) => *attached = Some(a),
+                            Err(e) => error!("cannot attach to iface({}): {}", interface.name, e),
+                        }
+                    });
+                    if let Err(e) = reattach.await {
+                        error!("cannot re-attach the classifiers: {}", e);
                     }
-                    _ => {}
                 }
+            });
+        } else if attached.lock().unwrap().is_none() {
+            warn!(
+                "iface({}) is missing and link_monitor is disabled",
+                self.iface_name
+            );
+        }
 
-                // If the state is still Ukn, this means we're Up.
-                if current == State::Ukn {
-                    _ = self.event_tx.send(State::Up).await;
-                    current = State::Up;
-                }
+        #[cfg(feature = "metrics")]
+        let metrics = {
+            let bpf = bpf.clone();
+            metrics::register(
+                &self.config,
+                &iface,
+                monotonic_ns,
+                self.probe_tx.subscribe(),
+                move || packets(&bpf.lock().unwrap()),
+            )?
+        };
+        let history = history::open(&self.config)?;
+        privileges::drop(&self.config)?;
 
-                let duration_overall = start_overall.elapsed();
-                // Perform three times more analysis than the rxtx_threshold.
-                // This is to avoid bad race condition where it would take
-                // more time than needed to detect outages.
-                tokio::time::sleep(
-                    Duration::from_millis(self.config.rxtx_threshold.div_ceil(3) as u64)
-                        - duration_overall,
-                )
-                .await;
+        // Only the packets tell the state of the cgroups.
+        let cgroup_config = Config {
+            probe_driven: false,
+            localize: false,
+            ..self.config.clone()
+        };
+        for (id, event_tx) in self.cgroups {
+            let bpf = bpf.clone();
+            let iface = iface.clone();
+            tokio::spawn(common::analyse(
+                cgroup_config.clone(),
+                event_tx,
+                common::Monitors {
+                    iface: iface.clone(),
+                    link: link.clone(),
+                    gateway: None,
+                    probes: None,
+                    egress: None,
+                    portal: None,
+                    dns: None,
+                    tcp: None,
+                    history: None,
+                    #[cfg(feature = "metrics")]
+                    metrics: None,
+                },
+                move || {
+                    if common::find_interface(&iface.borrow()).is_none() {
+                        return Ok(None);
+                    }
+                    cgroup::timestamps(&bpf.lock().unwrap(), id).map(Some)
+                },
+            ));
+        }
+
+        let detach = Detach {
+            bpf: bpf.clone(),
+            attached,
+        };
+        let egress = {
+            let bpf = bpf.clone();
+            move || {
+                let bpf = bpf.lock().unwrap();
+                let pkt_timestamp =
+                    HashMap::<_, u8, u64>::try_from(bpf.map("PKT_TIMESTAMP")?).ok()?;
+                value(pkt_timestamp.get(&2, 0)).ok()
             }
-        });
+        };
+        tokio::spawn(common::analyse(
+            self.config,
+            self.event_tx,
+            common::Monitors {
+                iface: iface.clone(),
+                link,
+                gateway,
+                probes,
+                egress: Some(Box::new(egress)),
+                portal,
+                dns,
+                tcp,
+                history,
+                #[cfg(feature = "metrics")]
+                metrics,
+            },
+            move || {
+                // The analysis owns it, until it stops.
+                let _ = &detach;
+                // Without the interface, the classifiers aren't attached anymore.
+                if common::find_interface(&iface.borrow()).is_none() {
+                    return Ok(None);
+                }
+
+                let bpf = bpf.lock().unwrap();
+                let pkt_timestamp =
+                    HashMap::<_, u8, u64>::try_from(bpf.map("PKT_TIMESTAMP").unwrap())?;
+
+                // Timestamps come from bpf_ktime_get_ns (CLOCK_MONOTONIC).
+                Ok(Some((
+                    value(pkt_timestamp.get(&0, 0))?,
+                    value(pkt_timestamp.get(&1, 0))?,
+                )))
+            },
+        ));
+
+        Ok(self.event_rx)
+    }
+
+    /// Run the detector on the maps pinned by the process monitoring the
+    /// interface, which attaches (and re-attaches) the classifiers. They're
+    /// opened again when that process restarts and pins new ones.
+    fn observe(self) -> Result<Receiver<Event>, anyhow::Error> {
+        if self.config.dns_monitor.is_some()
+            || self.config.tcp_monitor.is_some()
+            || !self.cgroups.is_empty()
+        {
+            warn!("dns_monitor, tcp_monitor and the cgroups are ignored by the observers");
+        }
+
+        let iface = common::start_iface_follower(&self.iface_name, &self.config);
+        let link = common::start_link_monitor(&iface, &self.config)?;
+        let gateway = common::start_gateway_monitor(&iface, &link, &self.config);
+        let portal = common::start_portal_monitor(&iface, &self.config);
+        let probes =
+            common::start_probes(&iface, monotonic_ns, &self.config, self.probe_tx.clone());
+        let dir = pin::dir(&self.iface_name);
+        let timestamps = Mutex::new(pin::Pinned::open(&dir, "PKT_TIMESTAMP")?);
+        let egress = Mutex::new(pin::Pinned::open(&dir, "PKT_TIMESTAMP")?);
+        #[cfg(feature = "metrics")]
+        let metrics = {
+            // Missing from the maps pinned by an older version.
+            let count = pin::Pinned::open(&dir, "PKT_COUNT").ok().map(Mutex::new);
+            metrics::register(
+                &self.config,
+                &iface,
+                monotonic_ns,
+                self.probe_tx.subscribe(),
+                move || {
+                    let mut count = count.as_ref()?.lock().unwrap();
+                    Some((
+                        count.get(0).unwrap_or_default(),
+                        count.get(1).unwrap_or_default(),
+                    ))
+                },
+            )?
+        };
+        let history = history::open(&self.config)?;
+        privileges::drop(&self.config)?;
+
+        tokio::spawn(common::analyse(
+            self.config,
+            self.event_tx,
+            common::Monitors {
+                iface: iface.clone(),
+                link,
+                gateway,
+                probes,
+                egress: Some(Box::new(move || egress.lock().unwrap().get(2).ok())),
+                portal,
+                dns: None,
+                tcp: None,
+                history,
+                #[cfg(feature = "metrics")]
+                metrics,
+            },
+            move || {
+                if common::find_interface(&iface.borrow()).is_none() {
+                    return Ok(None);
+                }
+
+                let mut timestamps = timestamps.lock().unwrap();
+                Ok(Some((timestamps.get(0)?, timestamps.get(1)?)))
+            },
+        ));
 
         Ok(self.event_rx)
     }
@@ -1 +1,9 @@
+mod cgroup;
 mod imple;
+mod pin;
+pub(crate) mod sys;
+mod tcp;
+mod tcx;
+mod xdp;
+
+pub(crate) use imple::load;
@@ -0,0 +1,131 @@
+//! Pinning of the maps under the directory of the interface (Config::pin),
+//! and their reading by the observers (Config::observer).
+
+use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+use std::path::{Path, PathBuf};
+
+use aya::maps::{HashMap, Map, MapData};
+use aya::{Bpf, Pod};
+
+use super::{imple::value, sys};
+use crate::dns_health::Counters;
+
+/// Where the maps are pinned, see Config::pin.
+const PIN_ROOT: &str = "/sys/fs/bpf/n-rt-onl";
+
+/// The maps of the eBPF object, all pinned.
+const MAPS: [&str; 10] = [
+    "PKT_TIMESTAMP",
+    "PKT_COUNT",
+    "DNS_PENDING",
+    "DNS_STATS",
+    "DNS_TIMEOUTS",
+    "TCP_STATS",
+    "IFACE_ADDRS",
+    "CGROUP_TIMESTAMP",
+    "IFACE_INDEX",
+    "TP_OFFSETS",
+];
+
+pub(super) fn dir(ifname: &str) -> PathBuf {
+    Path::new(PIN_ROOT).join(ifname)
+}
+
+/// Copy the entries of the map pinned by a previous run.
+fn copy<K: Pod, V: Pod>(bpf: &mut Bpf, name: &str, old: Map) -> Result<(), anyhow::Error> {
+    let old = HashMap::<_, K, V>::try_from(old)?;
+    let mut new = HashMap::<_, K, V>::try_from(bpf.map_mut(name).unwrap())?;
+    for (key, value) in old.iter().filter_map(Result::ok) {
+        new.insert(key, value, 0)?;
+    }
+
+    Ok(())
+}
+
+fn restore(bpf: &mut Bpf, name: &str, path: &Path) -> Result<(), anyhow::Error> {
+    match name {
+        "PKT_TIMESTAMP" | "PKT_COUNT" | "TCP_STATS" => {
+            copy::<u8, u64>(bpf, name, Map::HashMap(MapData::from_pin(path)?))
+        }
+        "DNS_STATS" => copy::<u32, Counters>(bpf, name, Map::HashMap(MapData::from_pin(path)?)),
+        "DNS_TIMEOUTS" => copy::<u32, u64>(bpf, name, Map::HashMap(MapData::from_pin(path)?)),
+        "CGROUP_TIMESTAMP" => {
+            copy::<u64, [u64; 2]>(bpf, name, Map::LruHashMap(MapData::from_pin(path)?))
+        }
+        // Filled again by the userspace, or short-lived.
+        _ => Ok(()),
+    }
+}
+
+/// Pin the maps of the (just loaded) object under the directory of the
+/// interface, carrying on the state of those pinned by a previous run.
+pub(super) fn pin(bpf: &mut Bpf, ifname: &str) -> Result<(), anyhow::Error> {
+    let dir = dir(ifname);
+    std::fs::create_dir_all(&dir)?;
+
+    for name in MAPS {
+        let path = dir.join(name);
+        if path.exists() {
+            // E.g. pinned by an older version with other sizes.
+            if let Err(e) = restore(bpf, name, &path) {
+                warn!("cannot restore {}, starting afresh: {}", path.display(), e);
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let fd = match bpf.map(name) {
+            Some(Map::HashMap(data)) | Some(Map::LruHashMap(data)) => data.as_raw_fd(),
+            _ => anyhow::bail!("{} is missing from the eBPF object", name),
+        };
+        sys::obj_pin(fd, &path)
+            .map_err(|e| anyhow::anyhow!("cannot pin {}: {}", path.display(), e))?;
+    }
+
+    Ok(())
+}
+
+/// A map pinned by the process monitoring the interface, opened again
+/// when that process restarts and pins new ones.
+pub(super) struct Pinned {
+    path: PathBuf,
+    ino: u64,
+    map: HashMap<MapData, u8, u64>,
+}
+
+impl Pinned {
+    pub(super) fn open(dir: &Path, name: &str) -> Result<Self, anyhow::Error> {
+        let path = dir.join(name);
+        let open = || -> Result<Self, anyhow::Error> {
+            let ino = std::fs::metadata(&path)?.ino();
+            let map = HashMap::try_from(Map::HashMap(MapData::from_pin(&path)?))?;
+            Ok(Pinned {
+                path: path.clone(),
+                ino,
+                map,
+            })
+        };
+
+        open().map_err(|e| {
+            anyhow::anyhow!(
+                "cannot open {}, is the interface monitored with pin? {}",
+                path.display(),
+                e
+            )
+        })
+    }
+
+    /// Value of `key`, from the map pinned now. 0 while missing.
+    pub(super) fn get(&mut self, key: u8) -> Result<u64, anyhow::Error> {
+        if std::fs::metadata(&self.path).is_ok_and(|m| m.ino() != self.ino) {
+            let dir = self.path.parent().unwrap().to_owned();
+            let name = self
+                .path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            *self = Pinned::open(&dir, &name)?;
+        }
+
+        Ok(value(self.map.get(&key, 0))?)
+    }
+}
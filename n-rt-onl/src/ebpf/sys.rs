@@ -0,0 +1,95 @@
+//! The bpf(2) commands aya doesn't provide.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::{
+    ffi::OsStrExt,
+    io::{FromRawFd, OwnedFd, RawFd},
+};
+use std::path::Path;
+
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_OBJ_PIN: libc::c_long = 6;
+const BPF_OBJ_GET: libc::c_long = 7;
+
+pub(crate) const BPF_PROG_TYPE_SCHED_CLS: u32 = 3;
+pub(crate) const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+pub(crate) const BPF_PROG_TYPE_XDP: u32 = 6;
+pub(crate) const BPF_PROG_TYPE_CGROUP_SKB: u32 = 8;
+
+/// bpf_attr of BPF_PROG_LOAD, up to the fields we use.
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoad {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+}
+
+/// bpf_attr of BPF_OBJ_PIN and BPF_OBJ_GET.
+#[repr(C)]
+#[derive(Default)]
+struct Obj {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+pub(super) fn bpf<T>(cmd: libc::c_long, attr: &mut T) -> io::Result<RawFd> {
+    let ret =
+        unsafe { libc::syscall(libc::SYS_bpf, cmd, attr as *mut T, std::mem::size_of::<T>()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret as RawFd)
+}
+
+/// Check the kernel can load programs of this type,
+/// by loading the smallest one: `r0 = 0; exit`.
+pub(crate) fn probe_prog_type(prog_type: u32) -> io::Result<()> {
+    let insns: [u64; 2] = [0xb7, 0x95];
+    let license = b"GPL\0";
+    let mut attr = ProgLoad {
+        prog_type,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        ..Default::default()
+    };
+    let fd = bpf(BPF_PROG_LOAD, &mut attr)?;
+    drop(unsafe { OwnedFd::from_raw_fd(fd) });
+
+    Ok(())
+}
+
+/// Pin the object (map, link...) at `path` in bpffs, it then outlives the process.
+pub(super) fn obj_pin(fd: RawFd, path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut attr = Obj {
+        pathname: path.as_ptr() as u64,
+        bpf_fd: fd as u32,
+        ..Default::default()
+    };
+    bpf(BPF_OBJ_PIN, &mut attr)?;
+
+    Ok(())
+}
+
+/// Open the object pinned at `path`.
+pub(super) fn obj_get(path: &Path) -> io::Result<OwnedFd> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut attr = Obj {
+        pathname: path.as_ptr() as u64,
+        ..Default::default()
+    };
+    let fd = bpf(BPF_OBJ_GET, &mut attr)?;
+
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
@@ -0,0 +1,225 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use aya::maps::HashMap;
+use aya::programs::TracePoint;
+use aya::Bpf;
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+use crate::{common, TcpMonitor, TcpStats};
+
+/// TCP_STATS keys.
+const TCP_SEGMENTS: u8 = 0;
+const TCP_RETRANSMITS: u8 = 1;
+const TCP_RESETS: u8 = 2;
+
+/// Where the tracefs is mounted, by default or on older kernels.
+const TRACEFS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// The fields read by the tracepoint programs, with their TP_OFFSETS key,
+/// the tracepoint and their size.
+const FIELDS: [(u8, &str, &str, u32); 7] = [
+    (0, "tcp/tcp_retransmit_skb", "family", 2),
+    (1, "tcp/tcp_retransmit_skb", "saddr", 4),
+    (2, "sock/inet_sock_set_state", "oldstate", 4),
+    (3, "sock/inet_sock_set_state", "newstate", 4),
+    (4, "sock/inet_sock_set_state", "family", 2),
+    (5, "sock/inet_sock_set_state", "protocol", 2),
+    (6, "sock/inet_sock_set_state", "saddr", 4),
+];
+
+/// Offset and size of `field` in the format of a tracepoint, where they're
+/// described by lines like:
+/// `field:__u8 saddr[4];	offset:34;	size:4;	signed:0;`
+fn find_field(format: &str, field: &str) -> Option<(u32, u32)> {
+    format.lines().find_map(|line| {
+        let mut parts = line.split(';').map(str::trim);
+        let name = parts.next()?.strip_prefix("field:")?;
+        let name = name.split_whitespace().last()?.split('[').next()?;
+        if name != field {
+            return None;
+        }
+        let offset = parts.next()?.strip_prefix("offset:")?.parse().ok()?;
+        let size = parts.next()?.strip_prefix("size:")?.parse().ok()?;
+        Some((offset, size))
+    })
+}
+
+/// Fill TP_OFFSETS with the offsets of the fields of `tracepoint`, which
+/// depend on the kernel.
+fn set_offsets(bpf: &mut Bpf, tracepoint: &str) -> Result<(), anyhow::Error> {
+    let format = TRACEFS
+        .iter()
+        .find_map(|tracefs| {
+            std::fs::read_to_string(format!("{}/events/{}/format", tracefs, tracepoint)).ok()
+        })
+        .ok_or_else(|| anyhow::anyhow!("cannot read the format of {}", tracepoint))?;
+
+    let mut offsets = HashMap::<_, u8, u32>::try_from(bpf.map_mut("TP_OFFSETS").unwrap())?;
+    for (key, _, name, expected) in FIELDS.iter().filter(|f| f.1 == tracepoint) {
+        match find_field(&format, name) {
+            Some((offset, size)) if size == *expected => offsets.insert(*key, offset, 0)?,
+            Some((_, size)) => anyhow::bail!("{} of {} is {} bytes", name, tracepoint, size),
+            None => anyhow::bail!("{} is missing from {}", name, tracepoint),
+        }
+    }
+
+    Ok(())
+}
+
+/// Load and attach the tracepoint programs.
+pub(super) fn attach(bpf: &mut Bpf, monitor: &TcpMonitor) -> Result<(), anyhow::Error> {
+    let mut tracepoints = vec![("n_rt_onl_tcp_retransmit", "tcp", "tcp_retransmit_skb")];
+    if monitor.resets {
+        tracepoints.push(("n_rt_onl_tcp_set_state", "sock", "inet_sock_set_state"));
+    }
+    for (name, category, tracepoint) in tracepoints {
+        set_offsets(bpf, &format!("{}/{}", category, tracepoint))?;
+        let program: &mut TracePoint = bpf.program_mut(name).unwrap().try_into()?;
+        program.load()?;
+        program.attach(category, tracepoint)?;
+    }
+
+    Ok(())
+}
+
+/// Make IFACE_ADDRS hold the IPv4 addresses of the interface,
+/// they tell the tracepoints which sockets go through it.
+fn sync_addresses(bpf: &mut Bpf, ifname: &str) -> Result<(), anyhow::Error> {
+    let mut addrs = HashMap::<_, u32, u8>::try_from(bpf.map_mut("IFACE_ADDRS").unwrap())?;
+    let current: Vec<u32> = common::find_interface(ifname)
+        .map(|interface| {
+            interface
+                .ips
+                .iter()
+                .filter_map(|ip| match ip.ip() {
+                    IpAddr::V4(ip) => Some(u32::from(ip)),
+                    IpAddr::V6(_) => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let stale: Vec<u32> = addrs
+        .keys()
+        .filter_map(Result::ok)
+        .filter(|addr| !current.contains(addr))
+        .collect();
+    for addr in stale {
+        addrs.remove(&addr)?;
+    }
+    for addr in current {
+        addrs.insert(addr, 1, 0)?;
+    }
+
+    Ok(())
+}
+
+/// Segments, retransmits and resets since the start.
+fn counters(bpf: &Bpf) -> Result<[u64; 3], anyhow::Error> {
+    let stats = HashMap::<_, u8, u64>::try_from(bpf.map("TCP_STATS").unwrap())?;
+
+    Ok([TCP_SEGMENTS, TCP_RETRANSMITS, TCP_RESETS]
+        .map(|key| stats.get(&key, 0).unwrap_or_default()))
+}
+
+/// Estimate the loss of the TCP traffic of the interface every interval,
+/// publishing the statistics on `stats_tx`. The receivers of the returned
+/// channel are notified when the loss becomes (or stops being) too high.
+pub(super) fn watch_tcp(
+    bpf: Arc<Mutex<Bpf>>,
+    iface: watch::Receiver<String>,
+    monitor: TcpMonitor,
+    stats_tx: watch::Sender<Option<TcpStats>>,
+) -> watch::Receiver<bool> {
+    let (degraded_tx, degraded_rx) = watch::channel(false);
+
+    tokio::spawn(async move {
+        let mut last = [0; 3];
+        let mut tick = tokio::time::interval(monitor.interval);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+
+            let current = {
+                let mut bpf = bpf.lock().unwrap();
+                let ifname = iface.borrow().clone();
+                if let Err(e) = sync_addresses(&mut bpf, &ifname) {
+                    warn!("cannot update the addresses of iface({}): {}", ifname, e);
+                }
+                counters(&bpf)
+            };
+            let current = match current {
+                Ok(current) => current,
+                Err(e) => {
+                    error!("cannot read the TCP statistics: {}", e);
+                    continue;
+                }
+            };
+            let [segments, retransmits, resets] =
+                [0, 1, 2].map(|i| current[i].saturating_sub(last[i]));
+            last = current;
+
+            let loss = match segments {
+                0 => 0.0,
+                segments => retransmits as f32 / segments as f32,
+            };
+            stats_tx.send_replace(Some(TcpStats {
+                segments,
+                retransmits,
+                resets,
+                loss,
+            }));
+
+            let degraded = segments >= monitor.min_segments && loss >= monitor.max_loss;
+            degraded_tx.send_if_modified(|current| {
+                if *current == degraded {
+                    return false;
+                }
+                if degraded {
+                    warn!(
+                        "TCP degraded: {} of {} segments retransmitted",
+                        retransmits, segments
+                    );
+                } else {
+                    info!("TCP recovered");
+                }
+                *current = degraded;
+                true
+            });
+        }
+    });
+
+    degraded_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SET_STATE: &str = "name: inet_sock_set_state
+ID: 1361
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:const void * skaddr;\toffset:8;\tsize:8;\tsigned:0;
+\tfield:int oldstate;\toffset:16;\tsize:4;\tsigned:1;
+\tfield:int newstate;\toffset:20;\tsize:4;\tsigned:1;
+\tfield:__u16 family;\toffset:28;\tsize:2;\tsigned:0;
+\tfield:__u16 protocol;\toffset:30;\tsize:2;\tsigned:0;
+\tfield:__u8 saddr_v6[16];\toffset:40;\tsize:16;\tsigned:0;
+\tfield:__u8 saddr[4];\toffset:32;\tsize:4;\tsigned:0;
+
+print fmt: \"family=%s protocol=%s\", ...
+";
+
+    #[test]
+    fn finds_the_fields() {
+        assert_eq!(find_field(SET_STATE, "skaddr"), Some((8, 8)));
+        assert_eq!(find_field(SET_STATE, "oldstate"), Some((16, 4)));
+        assert_eq!(find_field(SET_STATE, "protocol"), Some((30, 2)));
+        assert_eq!(find_field(SET_STATE, "saddr"), Some((32, 4)));
+        assert_eq!(find_field(SET_STATE, "daddr"), None);
+    }
+}
@@ -0,0 +1,69 @@
+//! TCX, the bpf_link based attachment of the TC programs (Linux 6.6+):
+//! the programs of several owners coexist without qdisc nor priority,
+//! and they're detached when their link is closed.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+
+use aya::programs::TcAttachType;
+
+use super::sys::{bpf, obj_get, obj_pin};
+use crate::common;
+
+const BPF_LINK_CREATE: libc::c_long = 28;
+
+const BPF_TCX_INGRESS: u32 = 46;
+const BPF_TCX_EGRESS: u32 = 47;
+/// Attach before the programs of the others, we only look at the packets.
+const BPF_F_BEFORE: u32 = 1 << 3;
+
+/// bpf_attr of BPF_LINK_CREATE for TCX.
+#[repr(C)]
+#[derive(Default)]
+struct LinkCreate {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+    relative_fd: u32,
+    _pad: u32,
+    expected_revision: u64,
+}
+
+/// Whether the kernel is recent enough for TCX.
+pub(super) fn supported() -> bool {
+    common::kernel_version() >= Some((6, 6))
+}
+
+/// Attach the (loaded) program to the interface, the returned
+/// link detaches it once closed, unless it's pinned.
+pub(super) fn attach(prog_fd: RawFd, index: u32, attach_type: TcAttachType) -> io::Result<File> {
+    let mut attr = LinkCreate {
+        prog_fd: prog_fd as u32,
+        target_ifindex: index,
+        attach_type: match attach_type {
+            TcAttachType::Ingress => BPF_TCX_INGRESS,
+            _ => BPF_TCX_EGRESS,
+        },
+        flags: BPF_F_BEFORE,
+        ..Default::default()
+    };
+    let fd = bpf(BPF_LINK_CREATE, &mut attr)?;
+
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Pin the link at `path`, it then outlives the process.
+pub(super) fn pin(link: &File, path: &Path) -> io::Result<()> {
+    obj_pin(link.as_raw_fd(), path)
+}
+
+/// Detach the link pinned at `path` by a previous run, if any.
+pub(super) fn unpin_stale(path: &Path) {
+    // Make sure it's a bpf object before removing it.
+    if obj_get(path).is_ok() && std::fs::remove_file(path).is_ok() {
+        info!("detached the stale link {}", path.display());
+    }
+}
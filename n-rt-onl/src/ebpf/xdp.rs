@@ -0,0 +1,46 @@
+use aya::programs::{xdp::XdpLinkId, Xdp, XdpFlags};
+use aya::Bpf;
+
+use crate::XdpMode;
+
+/// Replaces the ingress classifier, feeding the same maps.
+pub(super) const PROGRAM: &str = "n_rt_onl_xdp_ingress";
+
+pub(super) fn load(bpf: &mut Bpf) -> Result<(), anyhow::Error> {
+    let program: &mut Xdp = bpf.program_mut(PROGRAM).unwrap().try_into()?;
+    program.load()?;
+
+    Ok(())
+}
+
+/// Attach the (loaded) program to the interface, in native
+/// mode if the driver supports it with XdpMode::Auto.
+pub(super) fn attach(
+    bpf: &mut Bpf,
+    ifname: &str,
+    mode: XdpMode,
+) -> Result<XdpLinkId, anyhow::Error> {
+    let program: &mut Xdp = bpf.program_mut(PROGRAM).unwrap().try_into()?;
+    let flags = match mode {
+        XdpMode::Auto | XdpMode::Native => XdpFlags::DRV_MODE,
+        XdpMode::Skb => XdpFlags::SKB_MODE,
+    };
+    match program.attach(ifname, flags) {
+        Ok(link_id) => Ok(link_id),
+        Err(e) if mode == XdpMode::Auto => {
+            info!(
+                "iface({}) doesn't support native XDP, using the SKB mode: {}",
+                ifname, e
+            );
+            Ok(program.attach(ifname, XdpFlags::SKB_MODE)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(super) fn detach(bpf: &mut Bpf, link_id: XdpLinkId) {
+    let program: Result<&mut Xdp, _> = bpf.program_mut(PROGRAM).unwrap().try_into();
+    if let Ok(program) = program {
+        let _ = program.detach(link_id);
+    }
+}
@@ -0,0 +1,501 @@
+//! Durable record of the transitions (History), and the queries
+//! of the outages and availability over time (Timeline).
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::{Cause, Config, Event, History, Period, State, Timeline, Transition};
+
+/// (De)serialize a SystemTime as milliseconds since the Unix epoch.
+pub(crate) mod unix_ms {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let ms = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        serializer.serialize_u64(ms as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        Ok(UNIX_EPOCH + Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+/// Whether the state tells something about the connectivity. Error isn't
+/// recorded, and Ukn only marks when the monitoring started or stopped.
+pub(crate) fn known(state: &State) -> bool {
+    !matches!(state, State::Ukn | State::Error)
+}
+
+/// Whether the interface couldn't be used in the state: the time spent
+/// in it is downtime, and each period of it an outage.
+pub(crate) fn unavailable(state: &State) -> bool {
+    matches!(state, State::Down | State::Missing | State::CaptivePortal)
+}
+
+/// Open the journal of Config::history, if any.
+pub(crate) fn open(config: &Config) -> Result<Option<History>, anyhow::Error> {
+    config.history.as_ref().map(History::open).transpose()
+}
+
+fn read_transitions(file: &File) -> Vec<Transition> {
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(transition) => Some(transition),
+            // Most likely the last line, torn by a crash.
+            Err(e) => {
+                warn!("skipping the invalid history line {:?}: {}", line, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Whether the journal ends in the middle of a line, torn by a crash.
+fn torn(mut file: &File) -> io::Result<bool> {
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0];
+    file.read_exact(&mut last)?;
+
+    Ok(last[0] != b'\n')
+}
+
+impl History {
+    /// Open (or create) the journal at `path`,
+    /// recovering the last state of each interface.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("cannot open {}: {}", path.display(), e))?;
+
+        let mut last = HashMap::new();
+        let mut known_last = HashMap::new();
+        for transition in read_transitions(&file) {
+            if known(&transition.state) {
+                known_last.insert(transition.interface.clone(), transition.clone());
+            }
+            last.insert(transition.interface.clone(), transition);
+        }
+        let torn = torn(&file)?;
+
+        Ok(History {
+            file,
+            last,
+            known: known_last,
+            monitoring: None,
+            torn,
+        })
+    }
+
+    /// Read the journal at `path`, without writing to it.
+    pub fn read(path: impl AsRef<Path>) -> Result<Timeline, anyhow::Error> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|e| anyhow::anyhow!("cannot open {}: {}", path.display(), e))?;
+
+        Ok(Timeline::new(read_transitions(&file)))
+    }
+
+    /// Record the state of the event if it changed since the last one
+    /// recorded for `interface`, returning whether it was. The monitoring
+    /// of `interface` is marked as started first if it wasn't.
+    pub fn record(&mut self, interface: &str, event: &Event) -> Result<bool, anyhow::Error> {
+        if !known(&event.state) {
+            return Ok(false);
+        }
+        if self.monitoring.as_deref() != Some(interface) {
+            self.start(interface)?;
+        }
+        if self
+            .last
+            .get(interface)
+            .is_some_and(|last| last.state == event.state)
+        {
+            return Ok(false);
+        }
+
+        self.write(interface, event.state.clone(), event.cause.clone())?;
+
+        Ok(true)
+    }
+
+    /// Mark the monitoring of `interface` as started (stopping the one of
+    /// the interface monitored so far): its state is unknown, and the time
+    /// since it was last recorded unmonitored, until the next transition.
+    pub fn start(&mut self, interface: &str) -> Result<(), anyhow::Error> {
+        self.stop()?;
+        self.write(interface, State::Ukn, None)?;
+        self.monitoring = Some(interface.to_owned());
+
+        Ok(())
+    }
+
+    /// Mark the monitoring of the interface as stopped, done on drop.
+    pub fn stop(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(interface) = self.monitoring.take() {
+            self.write(&interface, State::Ukn, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        interface: &str,
+        state: State,
+        cause: Option<Cause>,
+    ) -> Result<(), anyhow::Error> {
+        let transition = Transition {
+            interface: interface.to_owned(),
+            state,
+            cause,
+            at: SystemTime::now(),
+        };
+        let mut line = serde_json::to_string(&transition)?;
+        line.push('\n');
+        // Finish the line torn by a crash, rather than appending to it.
+        if self.torn {
+            line.insert(0, '\n');
+        }
+        // One write per line so that the processes sharing the journal don't mix them.
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        self.torn = false;
+        if known(&transition.state) {
+            self.known.insert(interface.to_owned(), transition.clone());
+        }
+        self.last.insert(interface.to_owned(), transition);
+
+        Ok(())
+    }
+
+    /// The last period of `interface` with a known state in the journal,
+    /// e.g. an outage which started before a restart.
+    pub fn ongoing(&self, interface: &str) -> Option<Period> {
+        self.known.get(interface).map(|last| Period {
+            interface: last.interface.clone(),
+            state: last.state.clone(),
+            cause: last.cause.clone(),
+            start: last.at,
+            end: None,
+        })
+    }
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            error!("cannot record the end of the monitoring: {}", e);
+        }
+    }
+}
+
+impl Period {
+    /// Time spent in the state, up to now if it's ongoing.
+    pub fn duration(&self) -> Duration {
+        let end = self.end.unwrap_or_else(SystemTime::now);
+        end.duration_since(self.start).unwrap_or_default()
+    }
+
+    /// Time spent in the state between `from` and `to`.
+    pub fn overlap(&self, from: SystemTime, to: SystemTime) -> Duration {
+        let start = self.start.max(from);
+        let end = self.end.unwrap_or_else(SystemTime::now).min(to);
+        end.duration_since(start).unwrap_or_default()
+    }
+}
+
+impl Timeline {
+    pub fn new(mut transitions: Vec<Transition>) -> Self {
+        // The processes sharing a journal may append slightly out of order.
+        transitions.sort_by_key(|t| t.at);
+        Timeline { transitions }
+    }
+
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
+    }
+
+    /// The interfaces found in the journal.
+    pub fn interfaces(&self) -> Vec<String> {
+        let mut interfaces: Vec<String> = Vec::new();
+        for transition in &self.transitions {
+            if !interfaces.contains(&transition.interface) {
+                interfaces.push(transition.interface.clone());
+            }
+        }
+        interfaces
+    }
+
+    /// The periods of `interface` (of all if None) overlapping `from`..`to`,
+    /// each one lasting until the next transition of its interface. While
+    /// the interface wasn't monitored, between the end of the monitoring
+    /// (or a crash) and the next known state, the periods are Ukn.
+    pub fn periods(
+        &self,
+        interface: Option<&str>,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Vec<Period> {
+        let mut periods: Vec<Period> = Vec::new();
+        let mut current: HashMap<&str, usize> = HashMap::new();
+        for transition in &self.transitions {
+            if interface.is_some_and(|i| i != transition.interface) {
+                continue;
+            }
+            if let Some(&index) = current.get(transition.interface.as_str()) {
+                periods[index].end = Some(transition.at);
+            }
+            current.insert(&transition.interface, periods.len());
+            periods.push(Period {
+                interface: transition.interface.clone(),
+                state: transition.state.clone(),
+                cause: transition.cause.clone(),
+                start: transition.at,
+                end: None,
+            });
+        }
+
+        periods.retain(|p| p.start < to && p.end.is_none_or(|end| end > from));
+        periods
+    }
+
+    /// The outages (periods Down, Missing or CaptivePortal) of `interface`
+    /// (of all if None) overlapping `from`..`to`.
+    pub fn outages(
+        &self,
+        interface: Option<&str>,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Vec<Period> {
+        let mut periods = self.periods(interface, from, to);
+        periods.retain(|p| unavailable(&p.state));
+        periods
+    }
+
+    /// Fraction of the time `interface` wasn't in an outage between `from` and `to`,
+    /// out of the time it was monitored (its state was known). None if it wasn't. E.g. over the
+    /// last 30 days: `availability(iface, now - 30 * 86400s, now)`.
+    pub fn availability(&self, interface: &str, from: SystemTime, to: SystemTime) -> Option<f64> {
+        let mut monitored = Duration::ZERO;
+        let mut down = Duration::ZERO;
+        for period in self.periods(Some(interface), from, to) {
+            if !known(&period.state) {
+                continue;
+            }
+            let overlap = period.overlap(from, to);
+            monitored += overlap;
+            if unavailable(&period.state) {
+                down += overlap;
+            }
+        }
+
+        (!monitored.is_zero()).then(|| 1.0 - down.as_secs_f64() / monitored.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    /// A journal of its own for each test.
+    fn journal(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("n-rt-onl-{}-{}.jsonl", name, std::process::id()));
+        _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn states(path: &Path) -> Vec<(String, State)> {
+        History::read(path)
+            .unwrap()
+            .transitions()
+            .iter()
+            .map(|t| (t.interface.clone(), t.state.clone()))
+            .collect()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn transition(secs: u64, state: State) -> Transition {
+        Transition {
+            interface: String::from("eth0"),
+            state,
+            cause: None,
+            at: at(secs),
+        }
+    }
+
+    #[test]
+    fn marks_the_monitoring() {
+        let path = journal("marks");
+        let mut history = History::open(&path).unwrap();
+        let down = Event::new(State::Down, Some(Cause::RxTxGap));
+        assert!(history
+            .record("eth0", &Event::new(State::Up, None))
+            .unwrap());
+        assert!(!history
+            .record("eth0", &Event::new(State::Up, None))
+            .unwrap());
+        assert!(!history
+            .record("eth0", &Event::new(State::Error, None))
+            .unwrap());
+        assert!(history.record("eth0", &down).unwrap());
+        // E.g. following the default route.
+        assert!(history.record("wlan0", &down).unwrap());
+        drop(history);
+
+        let eth0 = |state| (String::from("eth0"), state);
+        let wlan0 = |state| (String::from("wlan0"), state);
+        assert_eq!(
+            states(&path),
+            [
+                eth0(State::Ukn),
+                eth0(State::Up),
+                eth0(State::Down),
+                eth0(State::Ukn),
+                wlan0(State::Ukn),
+                wlan0(State::Down),
+                wlan0(State::Ukn),
+            ]
+        );
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn recovers_the_outage_after_a_restart() {
+        let path = journal("restart");
+        let mut history = History::open(&path).unwrap();
+        history.start("eth0").unwrap();
+        let down = Event::new(State::Down, Some(Cause::LinkDown));
+        history.record("eth0", &down).unwrap();
+        drop(history);
+
+        let mut history = History::open(&path).unwrap();
+        let ongoing = history.ongoing("eth0").unwrap();
+        assert_eq!(ongoing.state, State::Down);
+        assert_eq!(ongoing.cause, Some(Cause::LinkDown));
+        assert_eq!(
+            ongoing.start,
+            History::read(&path).unwrap().transitions()[1].at
+        );
+        assert_eq!(history.ongoing("wlan0"), None);
+
+        // Still Down after the unmonitored gap.
+        history.start("eth0").unwrap();
+        assert!(history.record("eth0", &down).unwrap());
+        drop(history);
+        let states: Vec<State> = states(&path).into_iter().map(|(_, s)| s).collect();
+        assert_eq!(
+            states,
+            [
+                State::Ukn,
+                State::Down,
+                State::Ukn,
+                State::Ukn,
+                State::Down,
+                State::Ukn
+            ]
+        );
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn finishes_a_torn_line() {
+        let path = journal("torn");
+        let up = serde_json::to_string(&transition(1, State::Up)).unwrap();
+        std::fs::write(&path, format!("{}\n{{\"interface\":\"eth0\",\"sta", up)).unwrap();
+
+        let mut history = History::open(&path).unwrap();
+        assert_eq!(history.ongoing("eth0").map(|p| p.start), Some(at(1)));
+        history
+            .record("eth0", &Event::new(State::Down, None))
+            .unwrap();
+        drop(history);
+
+        let states: Vec<State> = states(&path).into_iter().map(|(_, s)| s).collect();
+        assert_eq!(states, [State::Up, State::Ukn, State::Down, State::Ukn]);
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn the_gaps_are_unmonitored() {
+        let timeline = Timeline::new(vec![
+            transition(0, State::Up),
+            transition(100, State::Ukn),
+            // Restarted, and Down since before the restart.
+            transition(150, State::Ukn),
+            transition(160, State::Down),
+            transition(200, State::Up),
+        ]);
+
+        let periods: Vec<_> = timeline
+            .periods(Some("eth0"), at(0), at(300))
+            .into_iter()
+            .map(|p| (p.state, p.start, p.end))
+            .collect();
+        assert_eq!(
+            periods,
+            [
+                (State::Up, at(0), Some(at(100))),
+                (State::Ukn, at(100), Some(at(150))),
+                (State::Ukn, at(150), Some(at(160))),
+                (State::Down, at(160), Some(at(200))),
+                (State::Up, at(200), None),
+            ]
+        );
+        // 40s Down out of 240s monitored.
+        assert_eq!(
+            timeline.availability("eth0", at(0), at(300)),
+            Some(1.0 - 40.0 / 240.0)
+        );
+        assert_eq!(timeline.availability("eth0", at(100), at(160)), None);
+        assert_eq!(timeline.outages(None, at(0), at(300)).len(), 1);
+    }
+
+    #[test]
+    fn replays_the_periods_in_the_window() {
+        let timeline = Timeline::new(vec![
+            transition(200, State::Up),
+            transition(0, State::Up),
+            transition(100, State::Down),
+            Transition {
+                interface: String::from("wlan0"),
+                ..transition(50, State::Missing)
+            },
+        ]);
+
+        assert_eq!(timeline.interfaces(), ["eth0", "wlan0"]);
+        let periods = timeline.periods(Some("eth0"), at(120), at(150));
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].state, State::Down);
+        assert_eq!(periods[0].duration(), Duration::from_secs(100));
+        assert_eq!(
+            periods[0].overlap(at(120), at(150)),
+            Duration::from_secs(30)
+        );
+        assert_eq!(timeline.periods(None, at(0), at(300)).len(), 4);
+    }
+}
@@ -3,16 +3,41 @@ extern crate log;
 
 #[cfg(all(target_os = "linux", not(feature = "userspace")))]
 use aya::Bpf;
-use pnet::datalink::{self, NetworkInterface};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
 use std::io::Error;
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use std::net::{IpAddr, SocketAddr};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, Receiver, Sender},
+    watch,
+};
 
 mod common;
+mod dns_health;
 #[cfg(all(target_os = "linux", not(feature = "userspace")))]
 mod ebpf;
+mod history;
+mod localize;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(target_os = "linux")]
+mod netlink;
+mod netns;
 #[cfg(any(feature = "userspace", not(target_os = "linux")))]
 mod other;
+mod preflight;
+#[cfg(target_os = "linux")]
+mod privileges;
+mod probe;
+mod report;
+mod suspend;
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum State {
@@ -20,6 +45,14 @@ pub enum State {
     Ukn,
     Down,
     Up,
+    /// The interface doesn't exist (yet or anymore).
+    Missing,
+    /// Packets flow but the traffic is intercepted (captive portal,
+    /// transparent proxy), see Config::captive_portal.
+    CaptivePortal,
+    /// Packets flow but too many TCP segments are retransmitted,
+    /// see Config::tcp_monitor.
+    Degraded,
 }
 
 impl From<usize> for State {
@@ -29,19 +62,513 @@ impl From<usize> for State {
             1 => State::Ukn,
             2 => State::Down,
             3 => State::Up,
+            4 => State::Missing,
+            5 => State::CaptivePortal,
+            6 => State::Degraded,
             _ => unreachable!(),
         }
     }
 }
 
+/// Why the state changed to Down.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Cause {
+    /// The gap between the last RX and TX packets exceeded rxtx_threshold.
+    RxTxGap,
+    /// The kernel reported the loss of carrier on the interface.
+    LinkDown,
+    /// The default route going through the interface was withdrawn.
+    RouteLost,
+    /// None of the probes succeeded during rxtx_threshold (probe_driven).
+    ProbesFailed,
+}
+
+/// Where the outage is, from the closest to the farthest layer.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum FailureScope {
+    /// The interface is missing or has no carrier.
+    Link,
+    /// The interface lost its global address or its default route.
+    Lan,
+    /// The gateway doesn't answer ARP or Neighbor Solicitations.
+    Gateway,
+    /// The gateway answers but neither the next hop nor the targets do.
+    Upstream,
+    /// The network is reachable but the DNS resolver doesn't answer.
+    Dns,
+    /// The next hop past the gateway answers, but some (or all) of the
+    /// icmp_targets and probes don't.
+    Target,
+}
+
+/// What changed besides the state, the events reporting
+/// a signal may not change the state.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Signal {
+    /// The gateway became reachable or unreachable, see Event::gateway.
+    Gateway,
+    /// Too many DNS queries seen in the traffic failed, see Config::dns_monitor.
+    DnsDegraded,
+    /// The DNS queries seen in the traffic work again.
+    DnsRecovered,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub state: State,
+    /// Only set when the state changes to Down.
+    pub cause: Option<Cause>,
+    /// Status of the interface at the time of the event,
+    /// None if the link monitoring is not available.
+    pub link: Option<LinkStatus>,
+    /// Only set when the state is Down and localize is enabled,
+    /// None if everything probed answered.
+    pub scope: Option<FailureScope>,
+    /// Status of the gateway at the time of the event,
+    /// None if it's not probed or unknown.
+    pub gateway: Option<GatewayStatus>,
+    pub signal: Option<Signal>,
+    /// Only set when the state is Down and the outage started before
+    /// a restart, according to Config::history.
+    pub since: Option<SystemTime>,
+}
+
+impl Event {
+    pub(crate) fn new(state: State, cause: Option<Cause>) -> Self {
+        Event {
+            state,
+            cause,
+            link: None,
+            scope: None,
+            gateway: None,
+            signal: None,
+            since: None,
+        }
+    }
+}
+
+/// Status of the interface as reported by the kernel.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LinkStatus {
+    /// The interface is running and its operstate allows traffic.
+    pub carrier: bool,
+    /// An IPv4 or IPv6 default route goes through the interface.
+    pub default_route: bool,
+    /// Gateway of the default route, None if it's on-link.
+    pub gateway: Option<IpAddr>,
+    /// The interface has an IPv4 or IPv6 address of global scope.
+    pub global_address: bool,
+    /// Index of the interface, None while it's missing.
+    pub index: Option<u32>,
+}
+
+/// Reachability of the gateway as seen by ARP or Neighbor Solicitations.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct GatewayStatus {
+    pub address: IpAddr,
+    /// At least one of the last 3 probes was answered.
+    pub reachable: bool,
+    /// Mean round-trip time of the answered probes among the last 10.
+    pub latency: Option<Duration>,
+    /// Fraction of the last 10 probes which weren't answered.
+    pub loss: f32,
+}
+
+/// What to probe and how.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum ProbeKind {
+    /// ICMP echo, unprivileged if allowed (net.ipv4.ping_group_range).
+    Icmp(IpAddr),
+    /// TCP connect.
+    Tcp(SocketAddr),
+    /// DNS query of the root NS over UDP, must be answered with NOERROR.
+    Dns(SocketAddr),
+    /// HTTP GET of a http:// URL, must be answered with a 2xx or 3xx.
+    Http(String),
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ProbeTarget {
+    pub kind: ProbeKind,
+    /// Time between the probes. Default to 1s.
+    pub interval: Duration,
+    /// Time after which a probe failed. Default to 1s.
+    pub timeout: Duration,
+    /// Random delay, up to this, added to each interval. Default to 0.
+    pub jitter: Duration,
+    /// How much the target counts in the quorum. Default to 1.
+    pub weight: u32,
+}
+
+impl ProbeTarget {
+    pub fn new(kind: ProbeKind) -> Self {
+        ProbeTarget {
+            kind,
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(1),
+            jitter: Duration::ZERO,
+            weight: 1,
+        }
+    }
+}
+
+/// How many probe targets decide the state when probe_driven is set.
+/// A target succeeded if one of its probes did during `window`,
+/// it failed if it was probed but none did, and counts for its weight.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Quorum {
+    /// Up once the targets which succeeded weigh at least this.
+    pub up: u32,
+    /// Down once the targets which failed weigh at least this,
+    /// unless the ones which succeeded are enough to be Up.
+    pub down: u32,
+    pub window: Duration,
+}
+
+/// Outcome of a probe, see Onl::probe_results.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub target: ProbeKind,
+    /// Round-trip time, None if the probe failed or timed out.
+    pub rtt: Option<Duration>,
+}
+
+/// Network namespace, see Config::netns.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub enum Netns {
+    /// Namespace created by `ip netns add`, under /var/run/netns.
+    Named(String),
+    /// Path of a namespace, e.g. /proc/<pid>/ns/net.
+    Path(PathBuf),
+    /// File descriptor of a namespace, still owned by the caller.
+    Fd(RawFd),
+}
+
+/// Unprivileged user to switch to once started, see Config::drop_privileges.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub struct DropPrivileges {
+    /// Name or uid of the user.
+    pub user: String,
+    /// Name or gid of the group, default to the primary group of the user.
+    pub group: Option<String>,
+    /// Deny the syscalls the monitor never needs once started (execve,
+    /// ptrace, mount, module loading...) to all the threads with seccomp.
+    /// Only on x86_64 and aarch64.
+    pub seccomp: bool,
+}
+
+/// How the classifiers are attached, see TcAttach.
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TcMode {
+    /// TCX when the kernel supports it (6.6+), netlink TC otherwise.
+    Auto,
+    /// TCX links, which coexist with the programs of the others.
+    Tcx,
+    /// Filters of the clsact qdisc, created if missing.
+    Netlink,
+}
+
+/// Attachment of the classifiers to the interface, see Config::tc_attach.
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct TcAttach {
+    pub mode: TcMode,
+    /// Priority of the netlink filters, 0 lets the kernel choose one.
+    pub priority: u16,
+    /// Handle of the netlink filters, 0 lets the kernel choose one.
+    pub handle: u32,
+}
+
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+impl Default for TcAttach {
+    fn default() -> Self {
+        TcAttach {
+            mode: TcMode::Auto,
+            priority: 0,
+            handle: 0,
+        }
+    }
+}
+
+/// Outcome of a check of Onl::preflight.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum CheckStatus {
+    Ok,
+    /// Works, with limitations or only for part of the features.
+    Warning,
+    /// Onl::start will fail.
+    Failed,
+    /// Not needed by the configuration.
+    Skipped,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// What will and won't work on this machine, see Onl::preflight.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PreflightReport {
+    pub checks: Vec<Check>,
+}
+
+impl PreflightReport {
+    /// Whether none of the checks failed.
+    pub fn ok(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Failed)
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warning => "warn",
+                CheckStatus::Failed => "FAIL",
+                CheckStatus::Skipped => "skip",
+            };
+            writeln!(f, "[{:>4}] {:<16} {}", status, check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// A change of state of an interface, as recorded by History. Ukn when
+/// its monitoring started or stopped, see Timeline::periods.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Transition {
+    pub interface: String,
+    pub state: State,
+    pub cause: Option<Cause>,
+    /// Milliseconds since the Unix epoch in the journal.
+    #[serde(with = "history::unix_ms")]
+    pub at: SystemTime,
+}
+
+/// Time an interface spent in a state, see Timeline.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Period {
+    pub interface: String,
+    pub state: State,
+    /// Why it went Down.
+    pub cause: Option<Cause>,
+    pub start: SystemTime,
+    /// None while it's ongoing.
+    pub end: Option<SystemTime>,
+}
+
+/// Append-only journal of the transitions, one JSON object per line,
+/// see Config::history. History::read gives the Timeline to query.
+#[derive(Debug)]
+pub struct History {
+    file: File,
+    /// Last transition of each interface.
+    last: HashMap<String, Transition>,
+    /// Last transition to a known state of each interface.
+    known: HashMap<String, Transition>,
+    /// Interface whose monitoring was marked as started.
+    monitoring: Option<String>,
+    /// The journal doesn't end with a newline.
+    torn: bool,
+}
+
+/// Transitions of a History ordered by time, to query
+/// the outages and the availability of the interfaces.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    transitions: Vec<Transition>,
+}
+
+/// Availability of an interface over a period, see Timeline::report.
+/// The durations are in seconds in JSON.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Report {
+    pub interface: String,
+    #[serde(with = "history::unix_ms")]
+    pub from: SystemTime,
+    #[serde(with = "history::unix_ms")]
+    pub to: SystemTime,
+    /// Time the state of the interface was known.
+    #[serde(with = "report::secs")]
+    pub monitored: Duration,
+    /// Time it was Down, Missing or behind a captive portal.
+    #[serde(with = "report::secs")]
+    pub downtime: Duration,
+    /// Fraction of the monitored time it was available, None if it wasn't monitored.
+    pub availability: Option<f64>,
+    /// Outages (periods of downtime) overlapping the period.
+    pub outages: u32,
+    /// Mean time between failures: the uptime divided by the outages.
+    #[serde(with = "report::opt_secs")]
+    pub mtbf: Option<Duration>,
+    /// Mean time to repair: the downtime divided by the outages.
+    #[serde(with = "report::opt_secs")]
+    pub mttr: Option<Duration>,
+    /// Whole duration of the longest outage, even outside the period.
+    #[serde(with = "report::opt_secs")]
+    pub longest_outage: Option<Duration>,
+    /// Breakdown per day (UTC).
+    pub days: Vec<DayReport>,
+}
+
+/// Availability of an interface during a day (UTC) of a Report.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct DayReport {
+    /// YYYY-MM-DD.
+    pub date: String,
+    #[serde(with = "report::secs")]
+    pub monitored: Duration,
+    #[serde(with = "report::secs")]
+    pub downtime: Duration,
+    pub availability: Option<f64>,
+    /// Outages which started that day, or before the report for its first day.
+    pub outages: u32,
+}
+
+/// How Report::render writes the reports.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ReportFormat {
+    /// Summary and breakdown per day, for humans.
+    Table,
+    Json,
+    /// One line per day and interface, then one for the whole period.
+    Csv,
+}
+
+/// Mode of the XDP program, see Config::xdp.
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum XdpMode {
+    /// Native when the driver supports it, SKB (generic) otherwise.
+    Auto,
+    Native,
+    /// Generic XDP, supported by every interface (e.g. veth).
+    Skb,
+}
+
+/// DNS statistics of a resolver, from the queries seen in the traffic
+/// since the start, or since the maps were first pinned with Config::pin.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ResolverStats {
+    pub resolver: IpAddr,
+    pub queries: u64,
+    pub responses: u64,
+    /// Queries without response after DnsMonitor::timeout.
+    pub timeouts: u64,
+    pub servfail: u64,
+    pub nxdomain: u64,
+    /// Mean time it took to get a response.
+    pub latency: Option<Duration>,
+}
+
+/// Passive monitoring of the DNS traffic (UDP and TCP port 53) of the interface.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DnsMonitor {
+    /// Time after which a query without response timed out. Default to 5s.
+    pub timeout: Duration,
+    /// Time the failure rate is computed on. Default to 30s.
+    pub window: Duration,
+    /// Fraction of the queries of the window which timed out or got
+    /// a SERVFAIL from which the DNS is degraded. Default to 0.5.
+    pub max_failures: f32,
+    /// Number of queries the window needs to judge. Default to 5.
+    pub min_queries: u64,
+}
+
+impl Default for DnsMonitor {
+    fn default() -> Self {
+        DnsMonitor {
+            timeout: Duration::from_secs(5),
+            window: Duration::from_secs(30),
+            max_failures: 0.5,
+            min_queries: 5,
+        }
+    }
+}
+
+/// TCP traffic of the interface during the last TcpMonitor::interval.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TcpStats {
+    /// Segments sent, including the retransmitted ones.
+    pub segments: u64,
+    pub retransmits: u64,
+    /// Connections reset (or which failed to be established).
+    pub resets: u64,
+    /// Estimation of the packet loss, the fraction of the segments
+    /// which were retransmitted.
+    pub loss: f32,
+}
+
+/// Monitoring of the TCP retransmissions of the sockets using an address of the
+/// interface, from the tcp:tcp_retransmit_skb tracepoint (eBPF only).
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TcpMonitor {
+    /// Time the loss is estimated on. Default to 5s.
+    pub interval: Duration,
+    /// Loss from which we're Degraded. Default to 0.1.
+    pub max_loss: f32,
+    /// Number of segments the interval needs to judge. Default to 20.
+    pub min_segments: u64,
+    /// Also count the connections reset with the sock:inet_sock_set_state
+    /// tracepoint. Default to true.
+    pub resets: bool,
+}
+
+impl Default for TcpMonitor {
+    fn default() -> Self {
+        TcpMonitor {
+            interval: Duration::from_secs(5),
+            max_loss: 0.1,
+            min_segments: 20,
+            resets: true,
+        }
+    }
+}
+
+/// Connectivity check telling whether we're behind a captive portal
+/// or a proxy which alters the traffic.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PortalCheck {
+    /// http:// URL to GET. Default to http://connectivitycheck.gstatic.com/generate_204.
+    pub url: String,
+    /// Status of the response when nothing intercepts it. Default to 204.
+    pub status: u16,
+    /// Body of the response when nothing intercepts it,
+    /// None to not check it. Default to an empty one.
+    pub body: Option<String>,
+    /// Time between the checks. Default to 30s.
+    pub interval: Duration,
+    /// Time after which a check failed. Default to 5s.
+    pub timeout: Duration,
+}
+
+impl Default for PortalCheck {
+    fn default() -> Self {
+        PortalCheck {
+            url: String::from("http://connectivitycheck.gstatic.com/generate_204"),
+            status: 204,
+            body: Some(String::new()),
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     #[cfg(all(target_os = "linux", not(feature = "userspace")))]
     /// Path to the ebpf program.
     pub ebpf_prog_path: String,
-    /// The MAX time difference in ms between RX/TX packets.
-    /// Default to 1500ms (1500000000ns).
-    pub rxtx_threshold: usize,
+    /// The MAX time difference between RX/TX packets.
+    /// Default to 1500ms.
+    pub rxtx_threshold: Duration,
 
     /// Determine if the library will send ICMP to specified
     /// servers as a sanity check for pkts reception. If your
@@ -49,7 +576,127 @@ pub struct Config {
     /// this may not be necessary. Otherwise, it is recommended
     /// to avoid false positive
     pub icmp_targets: Option<Vec<String>>,
-    pub icmp_interval: Option<u64>,
+    pub icmp_interval: Option<Duration>,
+
+    /// Targets probed in the background, along with the icmp_targets.
+    pub probes: Vec<ProbeTarget>,
+    /// Decide the state from the probes instead of the RX/TX packets:
+    /// Down when none of them succeeded during rxtx_threshold,
+    /// Up as soon as one does. Default to false.
+    pub probe_driven: bool,
+    /// Quorum of targets used by probe_driven. Default to None: Up as
+    /// soon as one target succeeded during rxtx_threshold, Down when none did.
+    pub quorum: Option<Quorum>,
+    /// Send the probes (and the ones of localize) through the monitored
+    /// interface whatever the routing table says, so that they check the
+    /// connectivity of this interface. A warning is logged if the probes
+    /// aren't seen leaving the interface. Default to true.
+    pub bind_probes: bool,
+
+    #[cfg(target_os = "linux")]
+    /// Watch the carrier, default routes and addresses of the interface
+    /// using rtnetlink, reporting Down as soon as the carrier or the default
+    /// route is lost instead of waiting for rxtx_threshold.
+    /// Default to true.
+    pub link_monitor: bool,
+
+    /// Switch to the interface carrying the default route
+    /// whenever it moves. Default to false.
+    pub follow_default_route: bool,
+
+    /// Probe the gateway, the next hop, the icmp_targets, the probes and the
+    /// DNS resolver when going Down to find where the outage is (Event::scope).
+    /// The next hop is looked for on the way to the first of them with an
+    /// address (or 1.1.1.1).
+    /// The probes and their replies are seen as traffic by the backends.
+    /// Default to false.
+    pub localize: bool,
+    /// DNS resolver probed by localize.
+    /// Default to the first nameserver of /etc/resolv.conf.
+    pub dns_resolver: Option<SocketAddr>,
+
+    /// Send an ARP request (or a Neighbor Solicitation) to the gateway at this
+    /// interval, reporting its reachability (Signal::Gateway). Default to None.
+    pub gateway_probe_interval: Option<Duration>,
+    /// Gateway to probe, default to the one of the default route
+    /// going through the interface (needs link_monitor).
+    pub gateway: Option<IpAddr>,
+
+    /// Check the connection isn't intercepted, reporting CaptivePortal instead
+    /// of Up when the response is redirected (3xx with a Location) or is an
+    /// altered 200. The other statuses leave the state as is. Default to None.
+    pub captive_portal: Option<PortalCheck>,
+
+    /// Match the DNS queries and responses of the interface, keeping statistics
+    /// per resolver (Onl::dns_stats) and sending Signal::DnsDegraded when too
+    /// many fail. Only IPv4 is supported. Default to None.
+    pub dns_monitor: Option<DnsMonitor>,
+
+    /// Estimate the packet loss from the TCP retransmissions (Onl::tcp_stats),
+    /// reporting Degraded instead of Up when it's too high. Only supported
+    /// by the eBPF backend. Default to None.
+    pub tcp_monitor: Option<TcpMonitor>,
+
+    /// Append the transitions to this journal (see History), with Ukn when
+    /// the monitoring starts and stops, and without Error. An outage still
+    /// going on when the process stopped is carried on (not counted twice,
+    /// see Event::since) if the interface is still Down once restarted.
+    /// Default to None.
+    pub history: Option<PathBuf>,
+
+    #[cfg(feature = "metrics")]
+    /// Serve the state, RX/TX ages, probe results, transitions and packet
+    /// counters in the Prometheus text format at http://<address>/metrics.
+    /// The Onls of the process sharing the address are served together,
+    /// labelled by interface. Default to None.
+    pub metrics: Option<SocketAddr>,
+
+    #[cfg(target_os = "linux")]
+    /// Network namespace of the interface (e.g. the one of a container),
+    /// where it's looked up, attached to and probed from. The events are
+    /// still delivered to the process. Entering it needs CAP_SYS_ADMIN.
+    /// It's used by the whole process, including the Onls created without
+    /// netns: interfaces of different namespaces can't be monitored by the
+    /// same process, Onl::new fails when given another one. Default to None.
+    pub netns: Option<Netns>,
+
+    #[cfg(target_os = "linux")]
+    /// Once the programs are attached and the capture opened, switch to this
+    /// user with only the capabilities still needed: CAP_NET_RAW for the raw
+    /// sockets, CAP_NET_ADMIN to re-attach and CAP_SYS_ADMIN for netns.
+    /// Default to None.
+    pub drop_privileges: Option<DropPrivileges>,
+
+    #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+    /// Root of the cgroup v2 hierarchy, where the programs of
+    /// Onl::subscribe_cgroup are attached. Default to /sys/fs/cgroup.
+    pub cgroup_root: PathBuf,
+
+    #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+    /// Pin the maps under /sys/fs/bpf/n-rt-onl/<iface> (bpffs must be mounted),
+    /// the timestamps survive the restarts and observers can read them.
+    /// <iface> is the name given to Onl::new, even if the interface is renamed
+    /// later, which is why it can't be used with follow_default_route.
+    /// Without it nothing is pinned. Default to false.
+    pub pin: bool,
+    #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+    /// Only read the maps pinned by another process monitoring the interface,
+    /// without loading nor attaching anything (CAP_BPF is enough to open them),
+    /// and opening them again when that process restarts.
+    /// dns_monitor, tcp_monitor, the cgroups and follow_default_route
+    /// aren't supported. Default to false.
+    pub observer: bool,
+    #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+    /// How the classifiers are attached. The filters already attached to the
+    /// interface are reported, the qdiscs of the others are left untouched.
+    /// Default to TCX when supported, netlink TC with a priority chosen by
+    /// the kernel otherwise.
+    pub tc_attach: TcAttach,
+    #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+    /// Timestamp the received packets with an XDP program instead of the
+    /// ingress classifier, as early as possible on high-rate interfaces.
+    /// The egress classifier is still attached. Default to None.
+    pub xdp: Option<XdpMode>,
 }
 
 impl Default for Config {
@@ -59,50 +706,134 @@ impl Default for Config {
             ebpf_prog_path: String::from("./target/bpfel-unknown-none/debug/n-rt-onl-ebpf"),
             #[cfg(all(not(debug_assertions), target_os = "linux", not(feature = "userspace")))]
             ebpf_prog_path: String::from("./target/bpfel-unknown-none/release/n-rt-onl-ebpf"),
-            rxtx_threshold: 1500,
+            rxtx_threshold: Duration::from_millis(1500),
             icmp_targets: None,
             icmp_interval: None,
+            probes: Vec::new(),
+            probe_driven: false,
+            quorum: None,
+            bind_probes: true,
+            #[cfg(target_os = "linux")]
+            link_monitor: true,
+            follow_default_route: false,
+            localize: false,
+            dns_resolver: None,
+            gateway_probe_interval: None,
+            gateway: None,
+            captive_portal: None,
+            dns_monitor: None,
+            tcp_monitor: None,
+            history: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(target_os = "linux")]
+            netns: None,
+            #[cfg(target_os = "linux")]
+            drop_privileges: None,
+            #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+            cgroup_root: PathBuf::from("/sys/fs/cgroup"),
+            #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+            pin: false,
+            #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+            observer: false,
+            #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+            tc_attach: TcAttach::default(),
+            #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+            xdp: None,
         }
     }
 }
 
 #[derive(Debug)]
 pub struct Onl {
-    event_rx: Receiver<State>,
-    event_tx: Sender<State>,
+    event_rx: Receiver<Event>,
+    event_tx: Sender<Event>,
+    probe_tx: broadcast::Sender<ProbeResult>,
+    dns_tx: watch::Sender<Vec<ResolverStats>>,
+    tcp_tx: watch::Sender<Option<TcpStats>>,
     iface_name: String,
     config: Config,
+    /// None for the observers, see Config::observer.
+    #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+    bpf: Option<Bpf>,
+    /// ID of the cgroups subscribed to and where to send their events.
     #[cfg(all(target_os = "linux", not(feature = "userspace")))]
-    bpf: Bpf,
+    cgroups: Vec<(u64, Sender<Event>)>,
 }
 
 impl Onl {
     pub fn new(ifname: String, config: Option<Config>) -> Result<Self, anyhow::Error> {
-        // Find the network interface with the provided name
-        let interface = datalink::interfaces()
-            .into_iter()
-            .find(|iface: &NetworkInterface| iface.name == ifname);
-
-        if interface.is_none() {
-            return Err(Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("iface({}) not found, check name and permissions", ifname),
-            )
-            .into());
+        let config = config.unwrap_or_default();
+        #[cfg(target_os = "linux")]
+        if let Some(ns) = &config.netns {
+            netns::set(ns)?;
+        }
+
+        // The interface can come and go (USB modems, VPNs, containers), so
+        // we'll report it as Missing and wait for it instead of failing.
+        if common::find_interface(&ifname).is_none() {
+            warn!(
+                "iface({}) not found, check name and permissions, waiting for it",
+                ifname
+            );
         }
 
         let channel = mpsc::channel(100);
-        let config = config.unwrap_or_default();
         #[cfg(all(target_os = "linux", not(feature = "userspace")))]
-        let bpf_path = config.ebpf_prog_path.clone();
+        let bpf = ebpf::load(&ifname, &config)?;
 
         Ok(Self {
             event_tx: channel.0,
             event_rx: channel.1,
+            probe_tx: broadcast::channel(100).0,
+            dns_tx: watch::channel(Vec::new()).0,
+            tcp_tx: watch::channel(None).0,
             iface_name: ifname,
             config,
             #[cfg(all(target_os = "linux", not(feature = "userspace")))]
-            bpf: Bpf::load_file(bpf_path).unwrap(),
+            bpf,
+            #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+            cgroups: Vec::new(),
         })
     }
+
+    /// Create an Onl monitoring the interface carrying the
+    /// IPv4 (or IPv6 as a fallback) default route.
+    pub fn for_default_route(config: Option<Config>) -> Result<Self, anyhow::Error> {
+        #[cfg(target_os = "linux")]
+        if let Some(ns) = config.as_ref().and_then(|c| c.netns.as_ref()) {
+            netns::set(ns)?;
+        }
+        let iface = match common::default_route_interface() {
+            Some(iface) => iface,
+            None => {
+                return Err(Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "no interface with a default route found",
+                )
+                .into())
+            }
+        };
+        info!("iface({}) carries the default route", iface.name);
+
+        Self::new(iface.name, config)
+    }
+
+    /// Subscribe to the results of the probes,
+    /// only those sent after the subscription are received.
+    pub fn probe_results(&self) -> broadcast::Receiver<ProbeResult> {
+        self.probe_tx.subscribe()
+    }
+
+    /// Watch the statistics of the resolvers seen in the traffic,
+    /// updated every second when Config::dns_monitor is set.
+    pub fn dns_stats(&self) -> watch::Receiver<Vec<ResolverStats>> {
+        self.dns_tx.subscribe()
+    }
+
+    /// Watch the TCP statistics of the interface, updated
+    /// every TcpMonitor::interval when Config::tcp_monitor is set.
+    pub fn tcp_stats(&self) -> watch::Receiver<Option<TcpStats>> {
+        self.tcp_tx.subscribe()
+    }
 }
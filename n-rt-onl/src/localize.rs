@@ -0,0 +1,142 @@
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use tokio::task::JoinHandle;
+
+use crate::{
+    common,
+    probe::{dns, icmp, neighbor, scheduler},
+    Cause, Config, FailureScope, LinkStatus, ProbeKind,
+};
+
+/// Hop we expect to be the first one past the gateway.
+const UPSTREAM_HOP: u8 = 2;
+
+/// Run a probe, returning whether it got an answer,
+/// or None if it couldn't be sent at all.
+fn spawn_probe<T, E, F>(what: String, probe: F) -> JoinHandle<Option<bool>>
+where
+    T: Debug + Send + 'static,
+    E: Display + Send + 'static,
+    F: Future<Output = Result<Option<T>, E>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        match probe.await {
+            Ok(Some(reply)) => {
+                debug!("localize: {} answered: {:?}", what, reply);
+                Some(true)
+            }
+            Ok(None) => {
+                debug!("localize: {} didn't answer", what);
+                Some(false)
+            }
+            Err(e) => {
+                warn!("localize: cannot probe {}: {}", what, e);
+                None
+            }
+        }
+    })
+}
+
+async fn answered(probe: Option<JoinHandle<Option<bool>>>) -> Option<bool> {
+    probe?.await.ok().flatten()
+}
+
+/// Find where the outage which made us go Down is, from the closest layer
+/// to the farthest: the link, the LAN setup, the gateway, the upstream
+/// network, the DNS resolver and finally the targets (Config::icmp_targets
+/// and Config::probes).
+/// Returns None if everything answered.
+pub(crate) async fn localize(
+    ifname: &str,
+    cause: &Cause,
+    status: Option<&LinkStatus>,
+    config: &Config,
+) -> Option<FailureScope> {
+    match (cause, status) {
+        (Cause::LinkDown, _) => return Some(FailureScope::Link),
+        (Cause::RouteLost, _) => return Some(FailureScope::Lan),
+        (_, Some(s)) if !s.global_address || !s.default_route => return Some(FailureScope::Lan),
+        _ => {}
+    }
+    let Some(interface) = common::find_interface(ifname) else {
+        return Some(FailureScope::Link);
+    };
+    let timeout = config.rxtx_threshold;
+    let bound = config.bind_probes.then(|| interface.clone());
+
+    // Probe everything at once, it takes at most `timeout`.
+    let gateway = status.and_then(|s| s.gateway).map(|gateway| {
+        spawn_probe(format!("gateway({})", gateway), async move {
+            // Reading from the datalink channel blocks.
+            tokio::task::spawn_blocking(move || neighbor::resolve(&interface, gateway, timeout))
+                .await?
+        })
+    });
+    let targets: Vec<ProbeKind> = config
+        .icmp_targets
+        .iter()
+        .flatten()
+        .filter_map(|t| t.parse().ok())
+        .map(ProbeKind::Icmp)
+        .chain(config.probes.iter().map(|p| p.kind.clone()))
+        .collect();
+    let upstream = targets
+        .iter()
+        .find_map(|t| match t {
+            ProbeKind::Icmp(ip) => Some(*ip),
+            ProbeKind::Tcp(addr) | ProbeKind::Dns(addr) => Some(addr.ip()),
+            ProbeKind::Http(_) => None,
+        })
+        .unwrap_or(match status.and_then(|s| s.gateway) {
+            Some(IpAddr::V6(_)) => {
+                IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111))
+            }
+            _ => IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+        });
+    let hop = spawn_probe(format!("hop {} to {}", UPSTREAM_HOP, upstream), {
+        let bound = bound.clone();
+        async move { icmp::ping(upstream, Some(UPSTREAM_HOP), bound.as_ref(), timeout).await }
+    });
+    let targets: Vec<_> = targets
+        .into_iter()
+        .map(|t| {
+            let bound = bound.clone();
+            spawn_probe(format!("target({:?})", t), async move {
+                scheduler::run(&t, bound.as_ref(), timeout).await
+            })
+        })
+        .collect();
+    let resolver = config
+        .dns_resolver
+        .or_else(dns::system_resolver)
+        .map(|resolver| {
+            let bound = bound.clone();
+            spawn_probe(format!("resolver({})", resolver), async move {
+                dns::query(resolver, bound.as_ref(), timeout).await
+            })
+        });
+
+    let gateway = answered(gateway).await;
+    let hop = answered(Some(hop)).await;
+    let mut targets_up = Vec::new();
+    for target in targets {
+        targets_up.push(answered(Some(target)).await);
+    }
+    let resolver = answered(resolver).await;
+
+    if gateway == Some(false) {
+        Some(FailureScope::Gateway)
+    } else if hop == Some(false) && !targets_up.contains(&Some(true)) {
+        Some(FailureScope::Upstream)
+    } else if resolver == Some(false) {
+        Some(FailureScope::Dns)
+    } else if targets_up.contains(&Some(false)) {
+        Some(FailureScope::Target)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,522 @@
+//! Prometheus exporter of what the analysis loops compute, see Config::metrics.
+//! The HTTP server only answers GET /metrics, it isn't worth a dependency.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch};
+use tokio::task::AbortHandle;
+
+use crate::{Config, ProbeKind, ProbeResult, State};
+
+/// Label of each state, in the order of From<usize>.
+const STATES: [&str; 7] = [
+    "error",
+    "ukn",
+    "down",
+    "up",
+    "missing",
+    "captive_portal",
+    "degraded",
+];
+
+/// Probe results kept per target for the RTT and the loss.
+const PROBE_WINDOW: usize = 10;
+
+/// Interfaces of all the Onls of the process, served on every address.
+static INTERFACES: Mutex<Vec<Arc<Interface>>> = Mutex::new(Vec::new());
+/// Addresses already listened on.
+static ADDRESSES: Mutex<Vec<SocketAddr>> = Mutex::new(Vec::new());
+
+type Packets = Box<dyn Fn() -> Option<(u64, u64)> + Send + Sync>;
+
+/// What's exported for an interface, fed by its analysis loop.
+pub(crate) struct Interface {
+    name: watch::Receiver<String>,
+    /// Time in the clock of the RX/TX timestamps.
+    now: fn() -> u64,
+    /// RX and TX packets seen by the backend.
+    packets: Packets,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    state: State,
+    since: Instant,
+    /// Transitions to each state.
+    transitions: [u64; STATES.len()],
+    /// Time spent Down, without the current outage.
+    outage: Duration,
+    timestamps: Option<(u64, u64)>,
+    probes: Vec<(ProbeKind, VecDeque<Option<Duration>>)>,
+}
+
+impl Interface {
+    /// Record the state, set by the analysis.
+    pub(crate) fn state(&self, state: &State) {
+        let inner = &mut *self.inner.lock().unwrap();
+        if inner.state == *state {
+            return;
+        }
+        if inner.state == State::Down {
+            inner.outage += inner.since.elapsed();
+        }
+        inner.transitions[state.clone() as usize] += 1;
+        inner.state = state.clone();
+        inner.since = Instant::now();
+    }
+
+    /// Record the last RX/TX timestamps, None while the interface is missing.
+    pub(crate) fn timestamps(&self, timestamps: Option<(u64, u64)>) {
+        self.inner.lock().unwrap().timestamps = timestamps;
+    }
+
+    fn probe(&self, result: ProbeResult) {
+        let inner = &mut *self.inner.lock().unwrap();
+        let index = match inner.probes.iter().position(|(k, _)| *k == result.target) {
+            Some(index) => index,
+            None => {
+                inner.probes.push((result.target, VecDeque::new()));
+                inner.probes.len() - 1
+            }
+        };
+        let results = &mut inner.probes[index].1;
+        if results.len() == PROBE_WINDOW {
+            results.pop_front();
+        }
+        results.push_back(result.rtt);
+    }
+}
+
+/// An interface served until its analysis loop drops it.
+pub(crate) struct Registered {
+    interface: Arc<Interface>,
+    /// Task recording its probe results.
+    probes: AbortHandle,
+}
+
+impl Deref for Registered {
+    type Target = Interface;
+
+    fn deref(&self) -> &Interface {
+        &self.interface
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        self.probes.abort();
+        INTERFACES
+            .lock()
+            .unwrap()
+            .retain(|i| !Arc::ptr_eq(i, &self.interface));
+    }
+}
+
+/// Register the interface whose analysis is started, and serve the metrics
+/// at Config::metrics if not already. Returns None without Config::metrics.
+pub(crate) fn register<F>(
+    config: &Config,
+    iface: &watch::Receiver<String>,
+    now: fn() -> u64,
+    mut probes: broadcast::Receiver<ProbeResult>,
+    packets: F,
+) -> Result<Option<Registered>, anyhow::Error>
+where
+    F: Fn() -> Option<(u64, u64)> + Send + Sync + 'static,
+{
+    let Some(address) = config.metrics else {
+        return Ok(None);
+    };
+
+    let mut addresses = ADDRESSES.lock().unwrap();
+    if !addresses.contains(&address) {
+        // Bound right away, before the privileges are dropped.
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        tokio::spawn(serve(TcpListener::from_std(listener)?));
+        info!("Serving the metrics at http://{}/metrics", address);
+        addresses.push(address);
+    }
+
+    let interface = Arc::new(Interface {
+        name: iface.clone(),
+        now,
+        packets: Box::new(packets),
+        inner: Mutex::new(Inner {
+            state: State::Ukn,
+            since: Instant::now(),
+            transitions: [0; STATES.len()],
+            outage: Duration::ZERO,
+            timestamps: None,
+            probes: Vec::new(),
+        }),
+    });
+    INTERFACES.lock().unwrap().push(interface.clone());
+
+    let probed = interface.clone();
+    let probes = tokio::spawn(async move {
+        loop {
+            match probes.recv().await {
+                Ok(result) => probed.probe(result),
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    Ok(Some(Registered {
+        interface,
+        probes: probes.abort_handle(),
+    }))
+}
+
+async fn serve(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((mut stream, peer)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = respond(&mut stream).await {
+                        debug!("metrics: cannot answer {}: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("metrics: cannot accept: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn respond(stream: &mut TcpStream) -> io::Result<()> {
+    // Only the request line matters, the scrapers don't send a body.
+    let mut buf = [0; 1024];
+    let mut len = 0;
+    let read = async {
+        while !buf[..len].windows(2).any(|w| w == b"\r\n") && len < buf.len() {
+            match stream.read(&mut buf[len..]).await? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        io::Result::Ok(())
+    };
+    tokio::time::timeout(Duration::from_secs(5), read)
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some(path)) if path.split('?').next() == Some("/metrics") => {
+            ("200 OK", render())
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", String::from("Not found\n")),
+        _ => ("400 Bad Request", String::from("Bad request\n")),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Values of an interface at the time of the scrape.
+struct Snapshot {
+    labels: String,
+    state: usize,
+    /// Age of the last RX and TX packets.
+    ages: Option<(f64, f64)>,
+    transitions: [u64; STATES.len()],
+    outage: Duration,
+    packets: Option<(u64, u64)>,
+    /// Labels, mean RTT and loss of each target.
+    probes: Vec<(String, Option<Duration>, f64)>,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn probe_labels(kind: &ProbeKind) -> String {
+    let (kind, target) = match kind {
+        ProbeKind::Icmp(ip) => ("icmp", ip.to_string()),
+        ProbeKind::Tcp(address) => ("tcp", address.to_string()),
+        ProbeKind::Dns(address) => ("dns", address.to_string()),
+        ProbeKind::Http(url) => ("http", url.clone()),
+    };
+    format!("kind=\"{}\",target=\"{}\"", kind, escape(&target))
+}
+
+fn snapshot(interface: &Interface) -> Snapshot {
+    let packets = (interface.packets)();
+    let inner = interface.inner.lock().unwrap();
+    let now = (interface.now)();
+    let age = |ts: u64| Duration::from_nanos(now.saturating_sub(ts)).as_secs_f64();
+
+    let mut outage = inner.outage;
+    if inner.state == State::Down {
+        outage += inner.since.elapsed();
+    }
+    let probes = inner
+        .probes
+        .iter()
+        .map(|(kind, results)| {
+            let rtts: Vec<Duration> = results.iter().flatten().copied().collect();
+            let rtt = (!rtts.is_empty()).then(|| rtts.iter().sum::<Duration>() / rtts.len() as u32);
+            let loss = 1.0 - rtts.len() as f64 / results.len() as f64;
+            (probe_labels(kind), rtt, loss)
+        })
+        .collect();
+
+    Snapshot {
+        labels: format!("interface=\"{}\"", escape(&interface.name.borrow())),
+        state: inner.state.clone() as usize,
+        ages: inner.timestamps.map(|(rx, tx)| (age(rx), age(tx))),
+        transitions: inner.transitions,
+        outage,
+        packets,
+        probes,
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    _ = writeln!(out, "# HELP {} {}", name, help);
+    _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// The metrics of all the interfaces, in the Prometheus text format.
+fn render() -> String {
+    let snapshots: Vec<Snapshot> = INTERFACES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|interface| snapshot(interface))
+        .collect();
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "n_rt_onl_state",
+        "gauge",
+        "Current state of the interface, 1 for the current one.",
+    );
+    for s in &snapshots {
+        for (i, state) in STATES.iter().enumerate() {
+            let value = (i == s.state) as u8;
+            _ = writeln!(
+                out,
+                "n_rt_onl_state{{{},state=\"{}\"}} {}",
+                s.labels, state, value
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "n_rt_onl_last_rx_age_seconds",
+        "gauge",
+        "Time since the last packet received.",
+    );
+    for s in &snapshots {
+        if let Some((rx, _)) = s.ages {
+            _ = writeln!(out, "n_rt_onl_last_rx_age_seconds{{{}}} {}", s.labels, rx);
+        }
+    }
+    header(
+        &mut out,
+        "n_rt_onl_last_tx_age_seconds",
+        "gauge",
+        "Time since the last packet sent.",
+    );
+    for s in &snapshots {
+        if let Some((_, tx)) = s.ages {
+            _ = writeln!(out, "n_rt_onl_last_tx_age_seconds{{{}}} {}", s.labels, tx);
+        }
+    }
+
+    header(
+        &mut out,
+        "n_rt_onl_probe_rtt_seconds",
+        "gauge",
+        "Mean round-trip time of the answered probes among the last 10.",
+    );
+    for s in &snapshots {
+        for (labels, rtt, _) in &s.probes {
+            if let Some(rtt) = rtt {
+                _ = writeln!(
+                    out,
+                    "n_rt_onl_probe_rtt_seconds{{{},{}}} {}",
+                    s.labels,
+                    labels,
+                    rtt.as_secs_f64()
+                );
+            }
+        }
+    }
+    header(
+        &mut out,
+        "n_rt_onl_probe_loss_ratio",
+        "gauge",
+        "Fraction of the last 10 probes which failed.",
+    );
+    for s in &snapshots {
+        for (labels, _, loss) in &s.probes {
+            _ = writeln!(
+                out,
+                "n_rt_onl_probe_loss_ratio{{{},{}}} {}",
+                s.labels, labels, loss
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "n_rt_onl_transitions_total",
+        "counter",
+        "Changes of the state, by new state.",
+    );
+    for s in &snapshots {
+        for (state, count) in STATES.iter().zip(s.transitions) {
+            _ = writeln!(
+                out,
+                "n_rt_onl_transitions_total{{{},state=\"{}\"}} {}",
+                s.labels, state, count
+            );
+        }
+    }
+    header(
+        &mut out,
+        "n_rt_onl_outage_seconds_total",
+        "counter",
+        "Time spent Down.",
+    );
+    for s in &snapshots {
+        _ = writeln!(
+            out,
+            "n_rt_onl_outage_seconds_total{{{}}} {}",
+            s.labels,
+            s.outage.as_secs_f64()
+        );
+    }
+
+    header(
+        &mut out,
+        "n_rt_onl_packets_total",
+        "counter",
+        "Packets seen by the classifiers (or the capture).",
+    );
+    for s in &snapshots {
+        if let Some((rx, tx)) = s.packets {
+            _ = writeln!(
+                out,
+                "n_rt_onl_packets_total{{{},direction=\"rx\"}} {}",
+                s.labels, rx
+            );
+            _ = writeln!(
+                out,
+                "n_rt_onl_packets_total{{{},direction=\"tx\"}} {}",
+                s.labels, tx
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(name: &str) -> (Registered, broadcast::Sender<ProbeResult>) {
+        let config = Config {
+            metrics: Some("127.0.0.1:0".parse().unwrap()),
+            ..Config::default()
+        };
+        let (_, iface) = watch::channel(name.to_owned());
+        let (probe_tx, probe_rx) = broadcast::channel(10);
+        let interface =
+            super::register(&config, &iface, || 3_000_000_000, probe_rx, || Some((5, 7)))
+                .unwrap()
+                .unwrap();
+
+        (interface, probe_tx)
+    }
+
+    async fn request(request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        respond(&mut server).await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn renders_the_registered_interfaces() {
+        let (interface, probe_tx) = register("render0");
+        interface.state(&State::Down);
+        interface.timestamps(Some((1_000_000_000, 2_000_000_000)));
+        probe_tx
+            .send(ProbeResult {
+                target: ProbeKind::Icmp("192.0.2.1".parse().unwrap()),
+                rtt: Some(Duration::from_millis(20)),
+            })
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let out = render();
+        for line in [
+            "n_rt_onl_state{interface=\"render0\",state=\"down\"} 1",
+            "n_rt_onl_state{interface=\"render0\",state=\"up\"} 0",
+            "n_rt_onl_last_rx_age_seconds{interface=\"render0\"} 2",
+            "n_rt_onl_last_tx_age_seconds{interface=\"render0\"} 1",
+            "n_rt_onl_probe_rtt_seconds{interface=\"render0\",kind=\"icmp\",target=\"192.0.2.1\"} 0.02",
+            "n_rt_onl_probe_loss_ratio{interface=\"render0\",kind=\"icmp\",target=\"192.0.2.1\"} 0",
+            "n_rt_onl_transitions_total{interface=\"render0\",state=\"down\"} 1",
+            "n_rt_onl_packets_total{interface=\"render0\",direction=\"rx\"} 5",
+            "n_rt_onl_packets_total{interface=\"render0\",direction=\"tx\"} 7",
+        ] {
+            assert!(out.lines().any(|l| l == line), "{} missing from:\n{}", line, out);
+        }
+
+        drop(interface);
+        assert!(!render().contains("render0"));
+    }
+
+    #[tokio::test]
+    async fn serves_the_metrics() {
+        let (_interface, _) = register("serve0");
+        let response = request("GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("interface=\"serve0\""));
+    }
+
+    #[tokio::test]
+    async fn only_serves_the_metrics() {
+        let response = request("GET /other HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request("POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
@@ -0,0 +1,30 @@
+use futures::TryStreamExt;
+use netlink_packet_route::{RtnlMessage, RT_SCOPE_UNIVERSE};
+use rtnetlink::Handle;
+
+/// Return the interface index concerned by an address notification.
+pub(super) fn address_index(message: &RtnlMessage) -> Option<u32> {
+    match message {
+        RtnlMessage::NewAddress(addr) | RtnlMessage::DelAddress(addr) => Some(addr.header.index),
+        _ => None,
+    }
+}
+
+/// Whether the interface `index` has an IPv4 or IPv6 address of global scope.
+pub(super) async fn has_global_address(
+    handle: &Handle,
+    index: u32,
+) -> Result<bool, rtnetlink::Error> {
+    let mut addrs = handle
+        .address()
+        .get()
+        .set_link_index_filter(index)
+        .execute();
+    while let Some(addr) = addrs.try_next().await? {
+        if addr.header.scope == RT_SCOPE_UNIVERSE {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
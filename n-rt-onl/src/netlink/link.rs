@@ -0,0 +1,106 @@
+use futures::TryStreamExt;
+use netlink_packet_route::{
+    link::nlas::{Nla, State as OperState},
+    LinkMessage, RtnlMessage, IFF_RUNNING,
+};
+use rtnetlink::Handle;
+
+fn name_of(link: &LinkMessage) -> Option<&str> {
+    link.nlas.iter().find_map(|nla| match nla {
+        Nla::IfName(name) => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+/// A link has carrier if it's running and its operstate doesn't say otherwise
+/// (virtual interfaces like tun often report Unknown).
+pub(super) fn has_carrier(link: &LinkMessage) -> bool {
+    let operstate_ok = link.nlas.iter().all(|nla| {
+        !matches!(
+            nla,
+            Nla::OperState(
+                OperState::Down
+                    | OperState::LowerLayerDown
+                    | OperState::NotPresent
+                    | OperState::Dormant
+            )
+        )
+    });
+
+    link.header.flags & IFF_RUNNING != 0 && operstate_ok
+}
+
+/// Return the index (None if deleted) and the carrier of the link
+/// if the notification is about `ifname`.
+pub(super) fn link_update(message: &RtnlMessage, ifname: &str) -> Option<(Option<u32>, bool)> {
+    let (link, update) = match message {
+        RtnlMessage::NewLink(link) => (link, (Some(link.header.index), has_carrier(link))),
+        RtnlMessage::DelLink(link) => (link, (None, false)),
+        _ => return None,
+    };
+
+    (name_of(link) == Some(ifname)).then_some(update)
+}
+
+/// Get the link named `ifname`.
+pub(super) async fn get_link(
+    handle: &Handle,
+    ifname: &str,
+) -> Result<Option<LinkMessage>, rtnetlink::Error> {
+    handle
+        .link()
+        .get()
+        .match_name(ifname.to_owned())
+        .execute()
+        .try_next()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(index: u32, running: bool, operstate: OperState) -> LinkMessage {
+        let mut link = LinkMessage::default();
+        link.header.index = index;
+        if running {
+            link.header.flags = IFF_RUNNING;
+        }
+        link.nlas = vec![Nla::IfName(String::from("eth0")), Nla::OperState(operstate)];
+        link
+    }
+
+    #[test]
+    fn carrier() {
+        assert!(has_carrier(&link(2, true, OperState::Up)));
+        // E.g. tun.
+        assert!(has_carrier(&link(2, true, OperState::Unknown)));
+        assert!(!has_carrier(&link(2, false, OperState::Up)));
+        for operstate in [
+            OperState::Down,
+            OperState::LowerLayerDown,
+            OperState::NotPresent,
+            OperState::Dormant,
+        ] {
+            assert!(!has_carrier(&link(2, true, operstate)));
+        }
+    }
+
+    #[test]
+    fn updates_of_the_interface() {
+        let up = RtnlMessage::NewLink(link(2, true, OperState::Up));
+        assert_eq!(link_update(&up, "eth0"), Some((Some(2), true)));
+        let down = RtnlMessage::NewLink(link(2, false, OperState::Down));
+        assert_eq!(link_update(&down, "eth0"), Some((Some(2), false)));
+        let deleted = RtnlMessage::DelLink(link(2, true, OperState::Up));
+        assert_eq!(link_update(&deleted, "eth0"), Some((None, false)));
+    }
+
+    #[test]
+    fn ignores_the_other_interfaces() {
+        let up = RtnlMessage::NewLink(link(3, true, OperState::Up));
+        assert_eq!(link_update(&up, "eth1"), None);
+        let route = RtnlMessage::NewRoute(Default::default());
+        assert_eq!(link_update(&route, "eth0"), None);
+    }
+}
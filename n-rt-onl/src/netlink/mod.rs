@@ -0,0 +1,183 @@
+use futures::{channel::mpsc::UnboundedReceiver, StreamExt};
+use netlink_packet_core::{NetlinkMessage, NetlinkPayload};
+use netlink_packet_route::RtnlMessage;
+use netlink_sys::{AsyncSocket, SocketAddr};
+use rtnetlink::{
+    constants::{
+        RTMGRP_IPV4_IFADDR, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_IFADDR, RTMGRP_IPV6_ROUTE, RTMGRP_LINK,
+    },
+    Handle,
+};
+use tokio::sync::watch;
+
+use crate::{common, netns, LinkStatus};
+
+mod addr;
+mod link;
+mod route;
+#[cfg(not(feature = "userspace"))]
+pub(crate) mod tc;
+
+type Messages = UnboundedReceiver<(NetlinkMessage<RtnlMessage>, SocketAddr)>;
+
+/// Open a rtnetlink connection subscribed to the multicast `groups`
+/// (RTMGRP_* constants), driven by a dedicated task.
+fn connect(groups: u32) -> Result<(Handle, Messages), anyhow::Error> {
+    let (mut conn, handle, messages) = netns::run(rtnetlink::new_connection)?;
+    conn.socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, groups))?;
+    tokio::spawn(conn);
+
+    Ok((handle, messages))
+}
+
+/// Keep the LinkStatus of the monitored interface up to date.
+struct LinkWatcher {
+    handle: Handle,
+    ifname: String,
+    index: Option<u32>,
+    status_tx: watch::Sender<LinkStatus>,
+}
+
+impl LinkWatcher {
+    /// Publish the new status, returns whether it changed.
+    fn publish(&self, new: LinkStatus) -> bool {
+        let changed = self.status_tx.send_if_modified(|status| {
+            if *status == new {
+                return false;
+            }
+            *status = new;
+            true
+        });
+        if changed {
+            debug!(
+                "iface({}) is now {:?}",
+                self.ifname,
+                *self.status_tx.borrow()
+            );
+        }
+        changed
+    }
+
+    /// Fill the routes and addresses part of the status from dumps.
+    async fn dump_l3(&self, index: u32, status: &mut LinkStatus) {
+        match route::default_route(&self.handle, index).await {
+            Ok((v, gateway)) => (status.default_route, status.gateway) = (v, gateway),
+            Err(e) => warn!("cannot dump routes from rtnetlink: {}", e),
+        }
+        match addr::has_global_address(&self.handle, index).await {
+            Ok(v) => status.global_address = v,
+            Err(e) => warn!("cannot dump addresses from rtnetlink: {}", e),
+        }
+    }
+
+    /// Get the whole status of the interface from dumps.
+    async fn resync(&mut self) {
+        let link = match link::get_link(&self.handle, &self.ifname).await {
+            Ok(link) => link,
+            Err(e) => {
+                warn!("cannot get iface({}) from rtnetlink: {}", self.ifname, e);
+                return;
+            }
+        };
+
+        self.index = link.as_ref().map(|l| l.header.index);
+        let mut status = LinkStatus {
+            carrier: link.as_ref().is_some_and(link::has_carrier),
+            default_route: false,
+            gateway: None,
+            global_address: false,
+            index: self.index,
+        };
+        if let Some(index) = self.index {
+            self.dump_l3(index, &mut status).await;
+        }
+        self.publish(status);
+    }
+
+    /// Update the status from a notification.
+    async fn handle(&mut self, message: RtnlMessage) {
+        let mut status = self.status_tx.borrow().clone();
+
+        if let Some((index, carrier)) = link::link_update(&message, &self.ifname) {
+            status.carrier = carrier;
+            status.index = index;
+            // The interface was (re-)created or deleted, get its routes and addresses.
+            if index != self.index {
+                self.index = index;
+                status.default_route = false;
+                status.gateway = None;
+                status.global_address = false;
+                if let Some(index) = index {
+                    self.dump_l3(index, &mut status).await;
+                }
+            }
+            self.publish(status);
+            return;
+        }
+
+        let Some(index) = self.index else {
+            return;
+        };
+        if route::is_default_route_msg(&message) || addr::address_index(&message) == Some(index) {
+            self.dump_l3(index, &mut status).await;
+            self.publish(status);
+        }
+    }
+}
+
+/// Watch the presence and carrier (RTMGRP_LINK), the default routes and the
+/// global addresses (RTMGRP_*_ROUTE and RTMGRP_*_IFADDR) of the monitored
+/// interface, following it if its name changes.
+pub(crate) fn watch_link(
+    mut iface: watch::Receiver<String>,
+) -> Result<watch::Receiver<LinkStatus>, anyhow::Error> {
+    // Subscribe before the initial dump so we don't miss any change.
+    let (handle, mut messages) = connect(
+        RTMGRP_LINK
+            | RTMGRP_IPV4_ROUTE
+            | RTMGRP_IPV6_ROUTE
+            | RTMGRP_IPV4_IFADDR
+            | RTMGRP_IPV6_IFADDR,
+    )?;
+    let ifname = iface.borrow_and_update().clone();
+    // Until rtnetlink tells us otherwise, assume the link is there if it exists.
+    let index = common::find_interface(&ifname).map(|i| i.index);
+    let (status_tx, status_rx) = watch::channel(LinkStatus {
+        carrier: true,
+        default_route: false,
+        gateway: None,
+        global_address: false,
+        index,
+    });
+
+    let mut watcher = LinkWatcher {
+        handle,
+        ifname,
+        index,
+        status_tx,
+    };
+    tokio::spawn(async move {
+        watcher.resync().await;
+
+        loop {
+            tokio::select! {
+                message = messages.next() => match message {
+                    Some((message, _)) => {
+                        if let NetlinkPayload::InnerMessage(message) = message.payload {
+                            watcher.handle(message).await;
+                        }
+                    }
+                    None => break,
+                },
+                Ok(()) = iface.changed() => {
+                    watcher.ifname = iface.borrow_and_update().clone();
+                    watcher.resync().await;
+                }
+            }
+        }
+    });
+
+    Ok(status_rx)
+}
@@ -0,0 +1,149 @@
+use std::net::IpAddr;
+
+use futures::TryStreamExt;
+use netlink_packet_route::{route::Nla, RouteMessage, RtnlMessage, RTN_UNICAST, RT_TABLE_LOCAL};
+use rtnetlink::{Handle, IpVersion};
+
+/// Return the output interfaces of the route if it's a default one:
+/// its own, or those of its next hops if it's a multipath one.
+pub(super) fn default_route_oifs(route: &RouteMessage) -> Vec<u32> {
+    if route.header.destination_prefix_length != 0
+        || route.header.kind != RTN_UNICAST
+        || route.header.table == RT_TABLE_LOCAL
+    {
+        return Vec::new();
+    }
+
+    route
+        .nlas
+        .iter()
+        .flat_map(|nla| match nla {
+            Nla::Oif(index) => vec![*index],
+            Nla::MultiPath(hops) => hops.iter().map(|hop| hop.interface_id).collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Whether the notification is about a default route.
+pub(super) fn is_default_route_msg(message: &RtnlMessage) -> bool {
+    match message {
+        RtnlMessage::NewRoute(route) | RtnlMessage::DelRoute(route) => {
+            !default_route_oifs(route).is_empty()
+        }
+        _ => false,
+    }
+}
+
+fn gateway_of(nlas: &[Nla]) -> Option<IpAddr> {
+    nlas.iter().find_map(|nla| match nla {
+        Nla::Gateway(bytes) => match bytes.len() {
+            4 => Some(IpAddr::from(<[u8; 4]>::try_from(&bytes[..]).ok()?)),
+            16 => Some(IpAddr::from(<[u8; 16]>::try_from(&bytes[..]).ok()?)),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Return the gateway of the route through the interface `index`
+/// (its next hop's if it's a multipath one), None if it's on-link.
+fn gateway(route: &RouteMessage, index: u32) -> Option<IpAddr> {
+    gateway_of(&route.nlas).or_else(|| {
+        route.nlas.iter().find_map(|nla| match nla {
+            Nla::MultiPath(hops) => hops
+                .iter()
+                .filter(|hop| hop.interface_id == index)
+                .find_map(|hop| gateway_of(&hop.nlas)),
+            _ => None,
+        })
+    })
+}
+
+/// Whether an IPv4 or IPv6 default route goes through the interface `index`,
+/// along with its gateway (the IPv4 one if there's both).
+pub(super) async fn default_route(
+    handle: &Handle,
+    index: u32,
+) -> Result<(bool, Option<IpAddr>), rtnetlink::Error> {
+    for version in [IpVersion::V4, IpVersion::V6] {
+        let mut routes = handle.route().get(version).execute();
+        while let Some(route) = routes.try_next().await? {
+            if default_route_oifs(&route).contains(&index) {
+                return Ok((true, gateway(&route, index)));
+            }
+        }
+    }
+
+    Ok((false, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use netlink_packet_route::{
+        route::{NextHop, NextHopBuffer},
+        RTA_GATEWAY, RTN_LOCAL, RT_TABLE_MAIN,
+    };
+    use netlink_packet_utils::Parseable;
+
+    use super::*;
+
+    fn route(prefix_length: u8, nlas: Vec<Nla>) -> RouteMessage {
+        let mut route = RouteMessage::default();
+        route.header.destination_prefix_length = prefix_length;
+        route.header.kind = RTN_UNICAST;
+        route.header.table = RT_TABLE_MAIN;
+        route.nlas = nlas;
+        route
+    }
+
+    fn hop(index: u32, gateway: [u8; 4]) -> NextHop {
+        // The rtnexthop, followed by its RTA_GATEWAY.
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&16u16.to_ne_bytes());
+        raw.extend_from_slice(&[0, 0]);
+        raw.extend_from_slice(&index.to_ne_bytes());
+        raw.extend_from_slice(&8u16.to_ne_bytes());
+        raw.extend_from_slice(&RTA_GATEWAY.to_ne_bytes());
+        raw.extend_from_slice(&gateway);
+
+        NextHop::parse(&NextHopBuffer::new(&raw)).unwrap()
+    }
+
+    #[test]
+    fn default_route() {
+        let route = route(0, vec![Nla::Gateway(vec![192, 0, 2, 1]), Nla::Oif(2)]);
+        assert_eq!(default_route_oifs(&route), [2]);
+        assert_eq!(gateway(&route, 2), Some(IpAddr::from([192, 0, 2, 1])));
+        assert!(is_default_route_msg(&RtnlMessage::DelRoute(route)));
+    }
+
+    #[test]
+    fn not_default_routes() {
+        let subnet = route(24, vec![Nla::Oif(2)]);
+        assert!(default_route_oifs(&subnet).is_empty());
+        assert!(!is_default_route_msg(&RtnlMessage::NewRoute(subnet)));
+
+        let mut local = route(0, vec![Nla::Oif(2)]);
+        local.header.kind = RTN_LOCAL;
+        assert!(default_route_oifs(&local).is_empty());
+        let mut local = route(0, vec![Nla::Oif(2)]);
+        local.header.table = RT_TABLE_LOCAL;
+        assert!(default_route_oifs(&local).is_empty());
+    }
+
+    #[test]
+    fn multipath_default_route() {
+        let hops = vec![hop(2, [192, 0, 2, 1]), hop(3, [198, 51, 100, 1])];
+        let route = route(0, vec![Nla::MultiPath(hops)]);
+        assert_eq!(default_route_oifs(&route), [2, 3]);
+        assert_eq!(gateway(&route, 3), Some(IpAddr::from([198, 51, 100, 1])));
+        assert!(is_default_route_msg(&RtnlMessage::NewRoute(route)));
+    }
+
+    #[test]
+    fn on_link_default_route() {
+        let route = route(0, vec![Nla::Oif(2)]);
+        assert_eq!(gateway(&route, 2), None);
+    }
+}
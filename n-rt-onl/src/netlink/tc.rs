@@ -0,0 +1,136 @@
+//! Synchronous view of the TC setup of an interface: the classifiers are
+//! attached from a locked Bpf, where the rtnetlink connection can't be awaited.
+
+use netlink_packet_core::{NetlinkMessage, NetlinkPayload, NLM_F_ACK, NLM_F_DUMP, NLM_F_REQUEST};
+use netlink_packet_route::{
+    tc::{
+        constants::{TC_H_CLSACT, TC_H_MAJ_MASK, TC_H_MIN_EGRESS, TC_H_MIN_INGRESS},
+        Nla,
+    },
+    RtnlMessage, TcMessage,
+};
+use netlink_sys::{protocols::NETLINK_ROUTE, Socket, SocketAddr};
+
+/// Handle of the clsact qdisc, ffff:.
+const CLSACT_HANDLE: u32 = TC_H_CLSACT & TC_H_MAJ_MASK;
+
+/// A filter attached by anyone to the clsact qdisc.
+#[derive(Debug)]
+pub(crate) struct Filter {
+    pub ingress: bool,
+    pub kind: String,
+    pub priority: u16,
+    pub handle: u32,
+}
+
+/// Send `message` and collect the messages of the reply, until the
+/// end of the dump or the acknowledgment.
+fn request(message: RtnlMessage, flags: u16) -> Result<Vec<RtnlMessage>, anyhow::Error> {
+    let mut socket = Socket::new(NETLINK_ROUTE)?;
+    socket.bind_auto()?;
+    socket.connect(&SocketAddr::new(0, 0))?;
+
+    let mut request = NetlinkMessage::from(message);
+    request.header.flags = NLM_F_REQUEST | flags;
+    request.finalize();
+    let mut buf = vec![0; request.header.length as usize];
+    request.serialize(&mut buf);
+    socket.send(&buf, 0)?;
+
+    let mut replies = Vec::new();
+    let mut buf = Vec::with_capacity(65536);
+    loop {
+        buf.clear();
+        let len = socket.recv(&mut buf, 0)?;
+        let mut offset = 0;
+        while offset + 4 <= len {
+            let length = u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+            if length == 0 || offset + length > len {
+                break;
+            }
+            let reply = NetlinkMessage::<RtnlMessage>::deserialize(&buf[offset..offset + length]);
+            offset += length;
+            // The options of some qdiscs can't be parsed, we don't need them.
+            let Ok(reply) = reply else {
+                continue;
+            };
+            match reply.payload {
+                NetlinkPayload::Done(_) => return Ok(replies),
+                NetlinkPayload::Error(e) => match e.code {
+                    Some(code) => return Err(std::io::Error::from_raw_os_error(-code.get()).into()),
+                    None => return Ok(replies),
+                },
+                NetlinkPayload::InnerMessage(message) => replies.push(message),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn kind_of(message: &TcMessage) -> String {
+    message
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Kind(kind) => Some(kind.clone()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Kinds of the root and ingress qdiscs of the interface.
+pub(crate) fn qdiscs(index: u32) -> Result<Vec<String>, anyhow::Error> {
+    let replies = request(
+        RtnlMessage::GetQueueDiscipline(TcMessage::default()),
+        NLM_F_DUMP,
+    )?;
+
+    Ok(replies
+        .into_iter()
+        .filter_map(|reply| match reply {
+            RtnlMessage::NewQueueDiscipline(qdisc) if qdisc.header.index == index as i32 => {
+                Some(kind_of(&qdisc))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// The filters of the clsact qdisc of the interface.
+pub(crate) fn filters(index: u32) -> Result<Vec<Filter>, anyhow::Error> {
+    let mut filters = Vec::new();
+    for (ingress, min) in [(true, TC_H_MIN_INGRESS), (false, TC_H_MIN_EGRESS)] {
+        let mut message = TcMessage::default();
+        message.header.index = index as i32;
+        message.header.parent = CLSACT_HANDLE | min;
+        for reply in request(RtnlMessage::GetTrafficFilter(message), NLM_F_DUMP)? {
+            let RtnlMessage::NewTrafficFilter(filter) = reply else {
+                continue;
+            };
+            // Each filter comes with a first message for its priority, without handle.
+            if filter.header.handle == 0 {
+                continue;
+            }
+            filters.push(Filter {
+                ingress,
+                kind: kind_of(&filter),
+                // The priority is in the upper half, the protocol in the lower one.
+                priority: (filter.header.info >> 16) as u16,
+                handle: filter.header.handle,
+            });
+        }
+    }
+
+    Ok(filters)
+}
+
+/// Delete the clsact qdisc of the interface, and all its filters.
+pub(crate) fn delete_clsact(index: u32) -> Result<(), anyhow::Error> {
+    let mut message = TcMessage::default();
+    message.header.index = index as i32;
+    message.header.parent = TC_H_CLSACT;
+    message.header.handle = CLSACT_HANDLE;
+    request(RtnlMessage::DelQueueDiscipline(message), NLM_F_ACK)?;
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! Network namespace of the monitored interface, see Config::netns.
+//! The namespace is per thread, so we only enter it the time to create
+//! the sockets (which stay in it) and to list the interfaces.
+
+#[cfg(target_os = "linux")]
+use std::{
+    fs::File,
+    io,
+    os::unix::{fs::MetadataExt, io::AsRawFd},
+    sync::OnceLock,
+};
+
+#[cfg(target_os = "linux")]
+use crate::Netns;
+
+/// The namespace, set once by Onl::new.
+#[cfg(target_os = "linux")]
+static NETNS: OnceLock<File> = OnceLock::new();
+
+#[cfg(target_os = "linux")]
+fn setns(ns: &File) -> io::Result<()> {
+    if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn open(netns: &Netns) -> io::Result<File> {
+    match netns {
+        Netns::Named(name) => File::open(format!("/var/run/netns/{}", name)),
+        Netns::Path(path) => File::open(path),
+        Netns::Fd(fd) => {
+            // Keep our own copy, the caller owns the fd.
+            let fd = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, 0) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(unsafe { std::os::unix::io::FromRawFd::from_raw_fd(fd) })
+        }
+    }
+}
+
+/// Run `f` in the namespace, checking it can be entered (and doesn't
+/// conflict with the one used) without using it for the process.
+#[cfg(target_os = "linux")]
+pub(crate) fn check<T>(netns: &Netns, f: impl FnOnce() -> T) -> Result<T, anyhow::Error> {
+    let ns = open(netns)?;
+    if let Some(current) = NETNS.get() {
+        if current.metadata()?.ino() != ns.metadata()?.ino() {
+            anyhow::bail!("another network namespace is already used");
+        }
+    }
+
+    Ok(run_in(&ns, f)?)
+}
+
+/// Use the namespace for the whole process. It can't change afterwards
+/// as the sockets created so far are in it.
+#[cfg(target_os = "linux")]
+pub(crate) fn set(netns: &Netns) -> Result<(), anyhow::Error> {
+    let ns = open(netns)?;
+    let ino = ns.metadata()?.ino();
+    if let Some(current) = NETNS.get() {
+        if current.metadata()?.ino() != ino {
+            anyhow::bail!("another network namespace is already used");
+        }
+        return Ok(());
+    }
+    // Make sure we can enter it (CAP_SYS_ADMIN) before relying on it.
+    run_in(&ns, || ())?;
+    _ = NETNS.set(ns);
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn run_in<T>(ns: &File, f: impl FnOnce() -> T) -> io::Result<T> {
+    let own = File::open("/proc/thread-self/ns/net")?;
+    setns(ns)?;
+    let ret = f();
+    // Failing to go back would leave the thread, and the runtime, in the namespace,
+    // all the sockets created afterwards would silently be in the wrong one.
+    if let Err(e) = setns(&own) {
+        error!(
+            "cannot go back to the network namespace of the thread: {}",
+            e
+        );
+        std::process::abort();
+    }
+
+    Ok(ret)
+}
+
+/// Run `f` in the namespace of the monitored interface, if any.
+pub(crate) fn run<T>(f: impl FnOnce() -> T) -> T {
+    #[cfg(target_os = "linux")]
+    if let Some(ns) = NETNS.get() {
+        let mut f = Some(f);
+        match run_in(ns, || (f.take().unwrap())()) {
+            Ok(ret) => return ret,
+            Err(e) => error!("cannot enter the network namespace: {}", e),
+        }
+        return (f.take().unwrap())();
+    }
+
+    f()
+}
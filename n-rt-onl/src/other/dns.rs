@@ -0,0 +1,167 @@
+use std::{collections::HashMap, net::Ipv4Addr, sync::Mutex};
+
+use crate::dns_health::{Counters, Query, Snapshot};
+
+pub(crate) const DNS_PORT: u16 = 53;
+
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Queries kept waiting for their response, as the map of the eBPF program.
+const MAX_PENDING: usize = 1024;
+
+/// Length of the header of the DNS messages.
+const HEADER_LEN: usize = 12;
+
+/// The message starting the payload of a TCP segment, where the messages
+/// are prefixed with their length. None for the bare ACKs and, as far as
+/// we can tell without following the stream, the segments continuing one.
+pub(crate) fn tcp_message(payload: &[u8]) -> Option<&[u8]> {
+    let length = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]) as usize;
+    let message = &payload[2..];
+    // A single question, like (almost) all the messages.
+    let qdcount = message.get(4..6)?;
+
+    (length >= HEADER_LEN
+        && message.len() >= HEADER_LEN
+        && message.len() <= length
+        && qdcount == [0, 1])
+    .then_some(message)
+}
+
+/// Beginning of a DNS message.
+pub(crate) struct Header {
+    pub id: u16,
+    pub response: bool,
+    pub rcode: u8,
+}
+
+impl Header {
+    pub(crate) fn parse(message: &[u8]) -> Option<Header> {
+        let header = message.get(..4)?;
+
+        Some(Header {
+            id: u16::from_be_bytes([header[0], header[1]]),
+            response: header[2] & 0x80 != 0,
+            rcode: header[3] & 0x0f,
+        })
+    }
+}
+
+/// The DNS queries seen on the interface and their outcome.
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    /// When each query was sent.
+    pending: Mutex<HashMap<Query, u64>>,
+    counters: Mutex<HashMap<Ipv4Addr, Counters>>,
+    /// Queries of each resolver which timed out.
+    timeouts: Mutex<HashMap<Ipv4Addr, u64>>,
+}
+
+impl Tracker {
+    pub(crate) fn query(&self, resolver: Ipv4Addr, port: u16, id: u16, now: u64) {
+        let query = Query {
+            resolver: resolver.into(),
+            port,
+            id,
+        };
+        let mut pending = self.pending.lock().unwrap();
+        // Only count the queries we'll see timing out, once if retransmitted.
+        if pending.len() >= MAX_PENDING || pending.contains_key(&query) {
+            return;
+        }
+        pending.insert(query, now);
+
+        let mut counters = self.counters.lock().unwrap();
+        counters.entry(resolver).or_default().queries += 1;
+    }
+
+    /// Match the response with its query, responses
+    /// to unknown (or timed out) queries are ignored.
+    pub(crate) fn response(&self, resolver: Ipv4Addr, port: u16, header: &Header, now: u64) {
+        let query = Query {
+            resolver: resolver.into(),
+            port,
+            id: header.id,
+        };
+        let Some(sent) = self.pending.lock().unwrap().remove(&query) else {
+            return;
+        };
+
+        let mut counters = self.counters.lock().unwrap();
+        let counters = counters.entry(resolver).or_default();
+        counters.responses += 1;
+        counters.latency_ns += now.saturating_sub(sent);
+        match header.rcode {
+            RCODE_SERVFAIL => counters.servfail += 1,
+            RCODE_NXDOMAIN => counters.nxdomain += 1,
+            _ => {}
+        }
+    }
+
+    /// Forget the queries sent before `before`, they timed out.
+    pub(crate) fn snapshot(&self, before: u64) -> Snapshot {
+        let mut timeouts = self.timeouts.lock().unwrap();
+        self.pending.lock().unwrap().retain(|query, sent| {
+            let waiting = *sent >= before;
+            if !waiting {
+                *timeouts.entry(Ipv4Addr::from(query.resolver)).or_default() += 1;
+            }
+            waiting
+        });
+        let counters = self.counters.lock().unwrap();
+
+        Snapshot {
+            counters: counters.iter().map(|(ip, c)| (*ip, *c)).collect(),
+            timeouts: timeouts.iter().map(|(ip, count)| (*ip, *count)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A query for example.com, prefixed with its length as over TCP.
+    const QUERY: [u8; 31] = [
+        0, 29, 0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p',
+        b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+    ];
+
+    #[test]
+    fn tcp_messages() {
+        let message = tcp_message(&QUERY).unwrap();
+        let header = Header::parse(message).unwrap();
+        assert_eq!(header.id, 0x1234);
+        assert!(!header.response);
+
+        // The first segment of a message longer than a segment.
+        assert!(tcp_message(&QUERY[..20]).is_some());
+    }
+
+    #[test]
+    fn not_tcp_messages() {
+        // Bare ACK.
+        assert!(tcp_message(&[]).is_none());
+        // Continuation of a message.
+        assert!(tcp_message(&QUERY[14..]).is_none());
+        // Longer than its length.
+        let mut longer = QUERY.to_vec();
+        longer.push(0);
+        assert!(tcp_message(&longer).is_none());
+        // Shorter than a header.
+        assert!(tcp_message(&QUERY[..10]).is_none());
+    }
+
+    #[test]
+    fn retransmitted_queries_count_once() {
+        let tracker = Tracker::default();
+        let resolver = Ipv4Addr::new(9, 9, 9, 9);
+        tracker.query(resolver, 40000, 1, 10);
+        tracker.query(resolver, 40000, 1, 20);
+
+        let snapshot = tracker.snapshot(15);
+        assert_eq!(snapshot.counters[0].1.queries, 1);
+        assert_eq!(snapshot.timeouts, [(resolver, 1)]);
+    }
+}
@@ -4,10 +4,16 @@ use pnet::datalink::NetworkInterface;
 use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
 use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::tcp::TcpPacket;
+use pnet::packet::udp::UdpPacket;
 use pnet::packet::Packet;
 use pnet::util::MacAddr;
 
-use super::{get_now_truncated, imple::GLOBAL_STATE};
+use super::{
+    dns::{self, Header, DNS_PORT},
+    get_now,
+    imple::GLOBAL_STATE,
+};
 
 const SUPPORTED_SENT_PROTO: [IpNextHeaderProtocol; 4] = [
     IpNextHeaderProtocols::Udp,
@@ -36,6 +42,41 @@ fn get_direction(source_mac: &MacAddr, interface: &NetworkInterface) -> PacketDi
     PacketDirection::Unknown
 }
 
+/// Match the DNS queries sent with the responses received.
+fn handle_dns(ip: &Ipv4Packet, is_sending: bool, now: u64) -> Option<()> {
+    let udp;
+    let tcp;
+    let (source, destination, message) = match ip.get_next_level_protocol() {
+        IpNextHeaderProtocols::Udp => {
+            udp = UdpPacket::new(ip.payload())?;
+            (udp.get_source(), udp.get_destination(), udp.payload())
+        }
+        IpNextHeaderProtocols::Tcp => {
+            tcp = TcpPacket::new(ip.payload())?;
+            (
+                tcp.get_source(),
+                tcp.get_destination(),
+                dns::tcp_message(tcp.payload())?,
+            )
+        }
+        _ => return None,
+    };
+    let header = Header::parse(message)?;
+
+    let dns = &GLOBAL_STATE.dns;
+    match (is_sending, header.response) {
+        (true, false) if destination == DNS_PORT => {
+            dns.query(ip.get_destination(), source, header.id, now)
+        }
+        (false, true) if source == DNS_PORT => {
+            dns.response(ip.get_source(), destination, &header, now)
+        }
+        _ => {}
+    }
+
+    Some(())
+}
+
 pub(crate) fn handle_ipv4_packet(
     source_mac: &MacAddr,
     interface: &NetworkInterface,
@@ -46,6 +87,16 @@ pub(crate) fn handle_ipv4_packet(
     if let Some(header) = header {
         let direction = get_direction(source_mac, interface);
         let is_sending = direction == PacketDirection::Sending;
+        if is_sending {
+            GLOBAL_STATE
+                .last_egress_pkt
+                .store(get_now(), Ordering::SeqCst);
+        }
+
+        // Before skipping anything, the resolver is often on the LAN.
+        if GLOBAL_STATE.dns_monitor.load(Ordering::SeqCst) {
+            handle_dns(&header, is_sending, get_now());
+        }
 
         let ip4_src = header.get_source();
         let ip4_dst = header.get_destination();
@@ -67,24 +118,18 @@ pub(crate) fn handle_ipv4_packet(
             debug!("Unsupported protocol: {}", protocol);
         }
 
-        let now_truncated = get_now_truncated();
+        let now = get_now();
         if is_sending && SUPPORTED_SENT_PROTO.contains(&protocol) {
-            GLOBAL_STATE
-                .last_tx_pkt
-                .store(now_truncated, Ordering::SeqCst);
+            GLOBAL_STATE.last_tx_pkt.store(now, Ordering::SeqCst);
         } else if !is_sending {
             // For each incoming packet, we suppose the network is "sane" so "reset" last_tx_pkt.
-            GLOBAL_STATE
-                .last_tx_pkt
-                .store(now_truncated, Ordering::SeqCst);
-            GLOBAL_STATE
-                .last_rx_pkt
-                .store(now_truncated, Ordering::SeqCst);
+            GLOBAL_STATE.last_tx_pkt.store(now, Ordering::SeqCst);
+            GLOBAL_STATE.last_rx_pkt.store(now, Ordering::SeqCst);
         }
 
         trace!(
             "{} -- {} - Packet: {:?} > {:?}",
-            now_truncated,
+            now,
             protocol,
             ip4_src,
             ip4_dst,
@@ -96,6 +141,11 @@ pub(crate) fn handle_ipv4_packet(
 
 pub(crate) fn handle_ethernet_frame(interface: &NetworkInterface, ethernet: &EthernetPacket) {
     let source_mac = ethernet.get_source();
+    #[cfg(feature = "metrics")]
+    match get_direction(&source_mac, interface) {
+        PacketDirection::Sending => GLOBAL_STATE.tx_packets.fetch_add(1, Ordering::Relaxed),
+        _ => GLOBAL_STATE.rx_packets.fetch_add(1, Ordering::Relaxed),
+    };
 
     #[allow(clippy::single_match)]
     match ethernet.get_ethertype() {
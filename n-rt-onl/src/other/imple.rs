@@ -1,133 +1,199 @@
 use std::{
-    io::Error,
-    sync::atomic::{AtomicUsize, Ordering},
+    io,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     time::Duration,
 };
 
 use once_cell::sync::Lazy;
 use pnet::{
-    datalink::{self, Channel::Ethernet, NetworkInterface},
+    datalink::{self, Channel::Ethernet, DataLinkReceiver, NetworkInterface},
     packet::ethernet::EthernetPacket,
 };
 use tokio::sync::mpsc::Receiver;
 
+#[cfg(feature = "metrics")]
+use crate::metrics;
+#[cfg(target_os = "linux")]
+use crate::privileges;
 use crate::{
-    common,
-    other::{frame, get_now_truncated},
-    Onl, State,
+    common, dns_health, history, netns,
+    other::{dns, frame, get_now},
+    Event, Onl, State,
 };
 
 pub(crate) static GLOBAL_STATE: Lazy<SharedData> = Lazy::new(SharedData::default);
 
 #[derive(Debug)]
 pub(crate) struct SharedData {
-    // last_xx_pkt is the Unix time in micros.
-    // can be truncated to fit in Usize.
-    pub last_rx_pkt: AtomicUsize,
-    pub last_tx_pkt: AtomicUsize,
+    // last_xx_pkt is the monotonic time in ns.
+    pub last_rx_pkt: AtomicU64,
+    pub last_tx_pkt: AtomicU64,
+    // Last packet sent, whatever its destination. Unlike last_tx_pkt
+    // it's not reset by the incoming packets.
+    pub last_egress_pkt: AtomicU64,
+    // whether the interface exists and the channel is opened.
+    pub present: AtomicBool,
+    // whether the DNS traffic is tracked, see Config::dns_monitor.
+    pub dns_monitor: AtomicBool,
+    pub dns: dns::Tracker,
+    // Frames captured, see Config::metrics.
+    #[cfg(feature = "metrics")]
+    pub rx_packets: AtomicU64,
+    #[cfg(feature = "metrics")]
+    pub tx_packets: AtomicU64,
 }
 
 impl Default for SharedData {
     fn default() -> Self {
         SharedData {
-            last_rx_pkt: get_now_truncated().into(),
-            last_tx_pkt: get_now_truncated().into(),
+            last_rx_pkt: get_now().into(),
+            last_tx_pkt: get_now().into(),
+            last_egress_pkt: get_now().into(),
+            present: false.into(),
+            dns_monitor: false.into(),
+            dns: dns::Tracker::default(),
+            #[cfg(feature = "metrics")]
+            rx_packets: 0.into(),
+            #[cfg(feature = "metrics")]
+            tx_packets: 0.into(),
         }
     }
 }
 
+/// Open the datalink channel on the interface, returning the receiving half.
+/// Reads time out after `read_timeout` so we can check if the interface changed.
+fn open_channel(
+    interface: &NetworkInterface,
+    read_timeout: Duration,
+) -> Result<Box<dyn DataLinkReceiver>, anyhow::Error> {
+    let config = datalink::Config {
+        read_timeout: Some(read_timeout),
+        ..Default::default()
+    };
+
+    match netns::run(|| datalink::channel(interface, config)) {
+        Ok(Ethernet(_, rx)) => Ok(rx),
+        Ok(_) => unreachable!("channel type not supported"),
+        Err(e) => Err(e.into()),
+    }
+}
+
 impl Onl {
     /// Start the outage notification process.
-    /// Returning the receiver of a MPSC channel.
-    pub fn start(self) -> Result<Receiver<State>, anyhow::Error> {
-        // Find the network interface with the provided name
-        let interface = match datalink::interfaces()
-            .into_iter()
-            .find(|iface: &NetworkInterface| iface.name == self.iface_name)
-        {
-            Some(itf) => itf,
-            None => {
-                return Err(Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "iface not found, check name and permissions",
-                )
-                .into())
+    /// Returning the receiver of a MPSC channel, the process
+    /// stops once it's dropped.
+    pub fn start(self) -> Result<Receiver<Event>, anyhow::Error> {
+        let retry_delay = self.config.rxtx_threshold;
+        // The interface may be missing for now, in which case we'll wait for it.
+        let mut channel = match common::find_interface(&self.iface_name) {
+            Some(interface) => {
+                let rx = open_channel(&interface, retry_delay)?;
+                GLOBAL_STATE.present.store(true, Ordering::SeqCst);
+                Some((interface, rx))
             }
+            None => None,
         };
 
-        let (_, mut rx) = match datalink::channel(&interface, Default::default()) {
-            Ok(Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => unreachable!("channel type not supported"),
-            Err(e) => return Err(e.into()),
-        };
-
-        // If some targets for icmp are specified, run the pinger
-        // Note: we don't care about the result, the eBPF prog will take care
-        // of that part.
-        if let Some(targets) = self.config.icmp_targets {
-            common::start_pinger(targets, self.config.icmp_interval);
+        let mut iface = common::start_iface_follower(&self.iface_name, &self.config);
+        let link = common::start_link_monitor(&iface, &self.config)?;
+        let gateway = common::start_gateway_monitor(&iface, &link, &self.config);
+        let portal = common::start_portal_monitor(&iface, &self.config);
+        if self.config.tcp_monitor.is_some() {
+            warn!("tcp_monitor is only supported by the eBPF backend");
         }
+        let dns = self.config.dns_monitor.clone().map(|monitor| {
+            GLOBAL_STATE.dns_monitor.store(true, Ordering::SeqCst);
+            dns_health::watch_dns(
+                monitor,
+                get_now,
+                |before| GLOBAL_STATE.dns.snapshot(before),
+                self.dns_tx.clone(),
+            )
+        });
+        // Probe the targets in the background, the traffic also makes sure
+        // we receive packets if the host doesn't have much to do.
+        let probes = common::start_probes(&iface, get_now, &self.config, self.probe_tx.clone());
+        #[cfg(feature = "metrics")]
+        let metrics = metrics::register(
+            &self.config,
+            &iface,
+            get_now,
+            self.probe_tx.subscribe(),
+            || {
+                Some((
+                    GLOBAL_STATE.rx_packets.load(Ordering::Relaxed),
+                    GLOBAL_STATE.tx_packets.load(Ordering::Relaxed),
+                ))
+            },
+        )?;
+        let history = history::open(&self.config)?;
+        #[cfg(target_os = "linux")]
+        privileges::drop(&self.config)?;
 
         // Clone the object we need in our task (those that needs to be).
         let cch_tx = self.event_tx.clone();
         // Task to launch analysis as per packets info
-        tokio::spawn(async move {
-            // Need some inner state to know if we're in an "outage" or not
-            let mut current = State::Ukn;
-            _ = cch_tx.send(State::Ukn).await;
-
-            // Delay the start of the analysis by rxtx_threshold.
-            // At first we don't have any stats, so no need to check anything
-            tokio::time::sleep(Duration::from_millis(self.config.rxtx_threshold as u64)).await;
-
-            loop {
-                let start_overall = std::time::Instant::now();
-
-                let rx_pkt = GLOBAL_STATE.last_rx_pkt.load(Ordering::SeqCst);
-                let tx_pkt = GLOBAL_STATE.last_tx_pkt.load(Ordering::SeqCst);
-                let abs_diff = rx_pkt.abs_diff(tx_pkt);
-
-                match current {
-                    State::Up | State::Ukn => {
-                        // If the diff is bigger than rxtx_threshold
-                        if abs_diff > self.config.rxtx_threshold * 1000 {
-                            debug!("State now DOWN");
-                            _ = cch_tx.send(State::Down).await;
-                            current = State::Down;
+        tokio::spawn(common::analyse(
+            self.config,
+            cch_tx,
+            common::Monitors {
+                iface: iface.clone(),
+                link,
+                gateway,
+                probes,
+                egress: Some(Box::new(|| {
+                    Some(GLOBAL_STATE.last_egress_pkt.load(Ordering::SeqCst))
+                })),
+                portal,
+                dns,
+                tcp: None,
+                history,
+                #[cfg(feature = "metrics")]
+                metrics,
+            },
+            || {
+                Ok(GLOBAL_STATE.present.load(Ordering::SeqCst).then(|| {
+                    (
+                        GLOBAL_STATE.last_rx_pkt.load(Ordering::SeqCst),
+                        GLOBAL_STATE.last_tx_pkt.load(Ordering::SeqCst),
+                    )
+                }))
+            },
+        ));
+
+        // Task for the handling of packets, reading from the channel blocks.
+        tokio::task::spawn_blocking(move || loop {
+            if self.event_tx.is_closed() {
+                break;
+            }
+            // (Re)open the channel once the interface is there.
+            let (interface, mut rx) = match channel.take() {
+                Some(channel) => channel,
+                None => {
+                    let name = iface.borrow_and_update().clone();
+                    match common::find_interface(&name)
+                        .map(|i| open_channel(&i, retry_delay).map(|rx| (i, rx)))
+                    {
+                        Some(Ok(channel)) => {
+                            info!("Capturing on iface({})", name);
+                            GLOBAL_STATE.present.store(true, Ordering::SeqCst);
+                            channel
                         }
-                    }
-                    State::Down => {
-                        if abs_diff < self.config.rxtx_threshold * 1000 {
-                            debug!("State now UP");
-                            _ = cch_tx.send(State::Up).await;
-                            current = State::Up;
+                        Some(Err(e)) => {
+                            error!("datalink::channel: cannot open: {}", e);
+                            std::thread::sleep(retry_delay);
+                            continue;
+                        }
+                        None => {
+                            std::thread::sleep(retry_delay);
+                            continue;
                         }
                     }
-                    _ => {}
-                }
-
-                // If the state is still Ukn, this means we're Up.
-                if current == State::Ukn {
-                    debug!("State now UP");
-                    _ = cch_tx.send(State::Up).await;
-                    current = State::Up;
                 }
+            };
 
-                let duration_overall = start_overall.elapsed();
-                // Perform three times more analysis than the rxtx_threshold.
-                // This is to avoid bad race condition where it would take
-                // more time than needed to detect outages.
-                tokio::time::sleep(
-                    Duration::from_millis(self.config.rxtx_threshold.div_ceil(3) as u64)
-                        - duration_overall,
-                )
-                .await;
-            }
-        });
-
-        // Task for the handling of packets
-        tokio::spawn(async move {
-            loop {
+            // Read until the interface goes away or we switch to another one.
+            while !iface.has_changed().unwrap_or(false) && !self.event_tx.is_closed() {
                 match rx.next() {
                     Ok(packet) => {
                         frame::handle_ethernet_frame(
@@ -135,9 +201,15 @@ impl Onl {
                             &EthernetPacket::new(packet).unwrap(),
                         );
                     }
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(_) if common::find_interface(&interface.name).is_none() => {
+                        info!("iface({}) is missing, waiting for it", interface.name);
+                        GLOBAL_STATE.present.store(false, Ordering::SeqCst);
+                        break;
+                    }
                     Err(e) => {
                         error!("datalink::channel: unknown error: {}", e);
-                        _ = self.event_tx.send(State::Error).await;
+                        _ = self.event_tx.blocking_send(Event::new(State::Error, None));
                     }
                 }
             }
@@ -1,13 +1,16 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Instant;
 
+use once_cell::sync::Lazy;
+
+mod dns;
 mod frame;
 mod imple;
 
-pub(crate) fn get_now_truncated() -> usize {
-    let now_unix = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_micros();
+/// Reference point of the monotonic clock used for the timestamps.
+static CLOCK_START: Lazy<Instant> = Lazy::new(Instant::now);
 
-    now_unix as usize
+/// Monotonic time in ns, so that wall clock jumps (NTP, ...)
+/// don't impact the analysis.
+pub(crate) fn get_now() -> u64 {
+    crate::common::to_ns(CLOCK_START.elapsed())
 }
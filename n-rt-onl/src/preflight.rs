@@ -0,0 +1,294 @@
+//! Checks of what the configuration needs from the machine, for the
+//! diagnostics (Onl::preflight) and before going any further.
+
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+use std::path::Path;
+
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+use crate::ebpf::sys;
+#[cfg(target_os = "linux")]
+use crate::netns;
+use crate::{common, Check, CheckStatus, Config, Onl, PreflightReport};
+
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+pub(crate) const CAP_NET_ADMIN: u32 = 12;
+#[cfg(target_os = "linux")]
+pub(crate) const CAP_NET_RAW: u32 = 13;
+#[cfg(target_os = "linux")]
+pub(crate) const CAP_SYS_ADMIN: u32 = 21;
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+const CAP_SYS_RESOURCE: u32 = 24;
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+const CAP_PERFMON: u32 = 38;
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+pub(crate) const CAP_BPF: u32 = 39;
+
+fn check(name: &str, status: CheckStatus, detail: impl Into<String>) -> Check {
+    Check {
+        name: name.to_owned(),
+        status,
+        detail: detail.into(),
+    }
+}
+
+/// Effective capabilities of the process.
+#[cfg(target_os = "linux")]
+fn capabilities() -> u64 {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn capability(name: &str, caps: u64, cap: u32, needed: Option<(CheckStatus, &str)>) -> Check {
+    match needed {
+        _ if caps & (1 << cap) != 0 => check(name, CheckStatus::Ok, "effective"),
+        Some((status, why)) => check(name, status, format!("missing, needed {}", why)),
+        None => check(name, CheckStatus::Skipped, "not needed"),
+    }
+}
+
+/// Whether the probes (or localize) send packets which need raw sockets.
+#[cfg(target_os = "linux")]
+pub(crate) fn raw_probes(config: &Config) -> bool {
+    config.icmp_targets.is_some()
+        || !config.probes.is_empty()
+        || config.localize
+        || config.gateway_probe_interval.is_some()
+}
+
+#[cfg(all(target_os = "linux", not(feature = "userspace")))]
+fn ebpf_checks(config: &Config, checks: &mut Vec<Check>) {
+    let release = common::kernel_release().unwrap_or_default();
+    let version = common::kernel_version().unwrap_or_default();
+    checks.push(match version {
+        v if v < (4, 18) => check(
+            "kernel",
+            CheckStatus::Failed,
+            format!("{}, the eBPF backend needs Linux 4.18+", release),
+        ),
+        v if v < (6, 6) => check(
+            "kernel",
+            CheckStatus::Ok,
+            format!("{}, TC filters (TCX needs Linux 6.6+)", release),
+        ),
+        _ => check("kernel", CheckStatus::Ok, format!("{}, TCX links", release)),
+    });
+
+    checks.push(if Path::new("/sys/kernel/btf/vmlinux").exists() {
+        check("btf", CheckStatus::Ok, "/sys/kernel/btf/vmlinux")
+    } else {
+        check(
+            "btf",
+            CheckStatus::Warning,
+            "/sys/kernel/btf/vmlinux missing, built without CONFIG_DEBUG_INFO_BTF",
+        )
+    });
+
+    checks.push(match std::fs::read(&config.ebpf_prog_path) {
+        Ok(object) if object.starts_with(b"\x7fELF") => {
+            check("ebpf object", CheckStatus::Ok, &config.ebpf_prog_path)
+        }
+        Ok(_) => check(
+            "ebpf object",
+            CheckStatus::Failed,
+            format!("{} isn't an ELF object", config.ebpf_prog_path),
+        ),
+        Err(e) => check(
+            "ebpf object",
+            CheckStatus::Failed,
+            format!("{}: {}", config.ebpf_prog_path, e),
+        ),
+    });
+
+    // The observers don't load anything.
+    let program_types = [
+        ("sched_cls", sys::BPF_PROG_TYPE_SCHED_CLS, !config.observer),
+        (
+            "xdp",
+            sys::BPF_PROG_TYPE_XDP,
+            !config.observer && config.xdp.is_some(),
+        ),
+        (
+            "tracepoint",
+            sys::BPF_PROG_TYPE_TRACEPOINT,
+            !config.observer && config.tcp_monitor.is_some(),
+        ),
+        (
+            "cgroup_skb",
+            sys::BPF_PROG_TYPE_CGROUP_SKB,
+            !config.observer,
+        ),
+    ];
+    for (name, prog_type, needed) in program_types {
+        let name = format!("prog {}", name);
+        if !needed {
+            checks.push(check(&name, CheckStatus::Skipped, "not needed"));
+            continue;
+        }
+        checks.push(match sys::probe_prog_type(prog_type) {
+            Ok(()) => check(&name, CheckStatus::Ok, "loadable"),
+            // Only needed by Onl::subscribe_cgroup.
+            Err(e) if prog_type == sys::BPF_PROG_TYPE_CGROUP_SKB => check(
+                &name,
+                CheckStatus::Warning,
+                format!("{}, subscribe_cgroup won't work", e),
+            ),
+            Err(e) => check(&name, CheckStatus::Failed, e.to_string()),
+        });
+    }
+
+    let caps = capabilities();
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlim) };
+    checks.push(if version >= (5, 11) {
+        check("memlock", CheckStatus::Ok, "accounted to the memory cgroup")
+    } else if rlim.rlim_cur == libc::RLIM_INFINITY
+        || rlim.rlim_max == libc::RLIM_INFINITY
+        || caps & (1 << CAP_SYS_RESOURCE) != 0
+    {
+        check("memlock", CheckStatus::Ok, "unlimited once started")
+    } else {
+        check(
+            "memlock",
+            CheckStatus::Warning,
+            format!(
+                "limited to {} bytes, the maps may fail to be created",
+                rlim.rlim_max
+            ),
+        )
+    });
+
+    // CAP_BPF and CAP_PERFMON were split from CAP_SYS_ADMIN in Linux 5.8.
+    let caps_bpf = if caps & (1 << CAP_SYS_ADMIN) != 0 {
+        caps | (1 << CAP_BPF) | (1 << CAP_PERFMON)
+    } else {
+        caps
+    };
+    checks.push(capability(
+        "CAP_BPF",
+        caps_bpf,
+        CAP_BPF,
+        Some((
+            CheckStatus::Failed,
+            "to load the programs and open the maps",
+        )),
+    ));
+    checks.push(capability(
+        "CAP_PERFMON",
+        caps_bpf,
+        CAP_PERFMON,
+        (!config.observer && config.tcp_monitor.is_some())
+            .then_some((CheckStatus::Failed, "by the tracepoints of tcp_monitor")),
+    ));
+    checks.push(capability(
+        "CAP_NET_ADMIN",
+        caps,
+        CAP_NET_ADMIN,
+        (!config.observer).then_some((CheckStatus::Failed, "to attach to the interface")),
+    ));
+    checks.push(capability(
+        "CAP_NET_RAW",
+        caps,
+        CAP_NET_RAW,
+        raw_probes(config).then_some((CheckStatus::Warning, "by some of the probes")),
+    ));
+}
+
+#[cfg(all(target_os = "linux", feature = "userspace"))]
+fn userspace_checks(checks: &mut Vec<Check>) {
+    checks.push(check(
+        "kernel",
+        CheckStatus::Ok,
+        common::kernel_release().unwrap_or_default(),
+    ));
+
+    checks.push(capability(
+        "CAP_NET_RAW",
+        capabilities(),
+        CAP_NET_RAW,
+        Some((CheckStatus::Failed, "to capture the packets")),
+    ));
+}
+
+impl Onl {
+    /// Check what `ifname` and `config` need from the machine before
+    /// creating the Onl: kernel, BPF support, capabilities, interface...
+    /// The Onl can be created and started if none of the checks failed.
+    pub fn preflight(ifname: &str, config: &Config) -> PreflightReport {
+        let mut checks = Vec::new();
+
+        #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+        checks.push(check("backend", CheckStatus::Ok, "eBPF"));
+        #[cfg(any(feature = "userspace", not(target_os = "linux")))]
+        checks.push(check("backend", CheckStatus::Ok, "userspace"));
+
+        #[cfg(all(target_os = "linux", not(feature = "userspace")))]
+        ebpf_checks(config, &mut checks);
+        #[cfg(all(target_os = "linux", feature = "userspace"))]
+        userspace_checks(&mut checks);
+
+        // Only look into the namespace, Onl::new uses it for the process.
+        #[cfg(target_os = "linux")]
+        let interface = match &config.netns {
+            Some(ns) => match netns::check(ns, || common::find_interface(ifname)) {
+                Ok(interface) => {
+                    checks.push(check("netns", CheckStatus::Ok, format!("{:?}", ns)));
+                    interface
+                }
+                Err(e) => {
+                    checks.push(check(
+                        "netns",
+                        CheckStatus::Failed,
+                        format!("{:?}: {} (needs CAP_SYS_ADMIN)", ns, e),
+                    ));
+                    None
+                }
+            },
+            None => common::find_interface(ifname),
+        };
+        #[cfg(not(target_os = "linux"))]
+        let interface = common::find_interface(ifname);
+
+        checks.push(match interface {
+            None => check(
+                "interface",
+                CheckStatus::Warning,
+                format!("{} not found, Missing until it appears", ifname),
+            ),
+            Some(iface) if iface.is_loopback() => check(
+                "interface",
+                CheckStatus::Warning,
+                format!(
+                    "{} is the loopback, only the local traffic goes through",
+                    ifname
+                ),
+            ),
+            // tun, WireGuard, PPP... the backends expect Ethernet frames.
+            Some(iface) if iface.mac.is_none() || iface.is_point_to_point() => check(
+                "interface",
+                CheckStatus::Failed,
+                format!("{} has no Ethernet header", ifname),
+            ),
+            Some(iface) => check(
+                "interface",
+                CheckStatus::Ok,
+                format!("{} (index {}), Ethernet", ifname, iface.index),
+            ),
+        });
+
+        #[cfg(not(target_os = "linux"))]
+        let _ = config;
+
+        PreflightReport { checks }
+    }
+}
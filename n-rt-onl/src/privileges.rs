@@ -0,0 +1,430 @@
+//! Switch to an unprivileged user once started, see Config::drop_privileges.
+//!
+//! The credentials are per thread and the runtime already has its threads:
+//! like the setuid of the libc, each thread is sent a signal whose handler
+//! switches it, keeping the capabilities which are still needed.
+
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "userspace"))]
+use crate::common;
+use crate::{preflight, Config, DropPrivileges};
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Signal of the threads, the first ones are used by the libc.
+fn signal() -> libc::c_int {
+    libc::SIGRTMIN() + 6
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// What the threads switch to.
+static UID: AtomicU32 = AtomicU32::new(0);
+static GID: AtomicU32 = AtomicU32::new(0);
+static CAPS: AtomicU64 = AtomicU64::new(0);
+/// Threads switched, and the first error (errno) they met.
+static SWITCHED: AtomicUsize = AtomicUsize::new(0);
+static ERRNO: AtomicI32 = AtomicI32::new(0);
+
+/// Only one switch at a time.
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
+
+/// Switch the calling thread, with raw syscalls: the wrappers of the
+/// libc would switch every thread, and we run from a signal handler.
+unsafe fn switch_thread() -> Result<(), i32> {
+    let uid = UID.load(Ordering::SeqCst);
+    let gid = GID.load(Ordering::SeqCst);
+    let caps = CAPS.load(Ordering::SeqCst);
+
+    // Keep the permitted capabilities across the setresuid.
+    if libc::syscall(libc::SYS_prctl, libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) != 0
+        || libc::syscall(libc::SYS_setgroups, 0, std::ptr::null::<libc::gid_t>()) != 0
+        || libc::syscall(libc::SYS_setresgid, gid, gid, gid) != 0
+        || libc::syscall(libc::SYS_setresuid, uid, uid, uid) != 0
+    {
+        return Err(errno());
+    }
+
+    let header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [
+        CapData {
+            effective: caps as u32,
+            permitted: caps as u32,
+            inheritable: 0,
+        },
+        CapData {
+            effective: (caps >> 32) as u32,
+            permitted: (caps >> 32) as u32,
+            inheritable: 0,
+        },
+    ];
+    if libc::syscall(libc::SYS_capset, &header, data.as_ptr()) != 0
+        || libc::syscall(libc::SYS_prctl, libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) != 0
+    {
+        return Err(errno());
+    }
+
+    Ok(())
+}
+
+extern "C" fn on_signal(_: libc::c_int) {
+    let saved = errno();
+    if let Err(e) = unsafe { switch_thread() } {
+        let _ = ERRNO.compare_exchange(0, e, Ordering::SeqCst, Ordering::SeqCst);
+    }
+    SWITCHED.fetch_add(1, Ordering::SeqCst);
+    unsafe { *libc::__errno_location() = saved };
+}
+
+/// Permitted capabilities of the calling thread.
+fn permitted() -> Result<u64, anyhow::Error> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(data[0].permitted as u64 | (data[1].permitted as u64) << 32)
+}
+
+/// The capabilities still needed once started.
+fn needed(config: &Config) -> u64 {
+    let mut caps = 0;
+    // The capture and some of the probes use raw sockets.
+    if cfg!(feature = "userspace") || preflight::raw_probes(config) {
+        caps |= 1 << preflight::CAP_NET_RAW;
+    }
+    // To re-attach when the interface is re-created.
+    #[cfg(not(feature = "userspace"))]
+    if !config.observer && (config.link_monitor || config.follow_default_route) {
+        caps |= 1 << preflight::CAP_NET_ADMIN;
+    }
+    // To read the maps, unless unprivileged BPF is allowed. CAP_BPF was
+    // split from CAP_SYS_ADMIN in Linux 5.8.
+    #[cfg(not(feature = "userspace"))]
+    if common::kernel_version() >= Some((5, 8)) {
+        caps |= 1 << preflight::CAP_BPF;
+    } else {
+        caps |= 1 << preflight::CAP_SYS_ADMIN;
+    }
+    // To enter the namespace each time a socket is created.
+    if config.netns.is_some() {
+        caps |= 1 << preflight::CAP_SYS_ADMIN;
+    }
+
+    caps
+}
+
+/// Resolve a user (or group) given by name or id with `lookup`.
+fn resolve<T>(name: &str, lookup: impl FnOnce(&CStr) -> Option<T>) -> Option<T> {
+    lookup(&CString::new(name).ok()?)
+}
+
+fn user(name: &str) -> Result<(libc::uid_t, libc::gid_t), anyhow::Error> {
+    let by_name = resolve(name, |name| {
+        let pw = unsafe { libc::getpwnam(name.as_ptr()) };
+        (!pw.is_null()).then(|| unsafe { ((*pw).pw_uid, (*pw).pw_gid) })
+    });
+    let by_id = || {
+        let uid = name.parse().ok()?;
+        let pw = unsafe { libc::getpwuid(uid) };
+        let gid = match pw.is_null() {
+            true => uid,
+            false => unsafe { (*pw).pw_gid },
+        };
+        Some((uid, gid))
+    };
+
+    by_name
+        .or_else(by_id)
+        .ok_or_else(|| anyhow::anyhow!("unknown user {}", name))
+}
+
+fn group(name: &str) -> Result<libc::gid_t, anyhow::Error> {
+    resolve(name, |name| {
+        let gr = unsafe { libc::getgrnam(name.as_ptr()) };
+        (!gr.is_null()).then(|| unsafe { (*gr).gr_gid })
+    })
+    .or_else(|| name.parse().ok())
+    .ok_or_else(|| anyhow::anyhow!("unknown group {}", name))
+}
+
+fn thread_ids() -> Result<Vec<libc::pid_t>, anyhow::Error> {
+    Ok(std::fs::read_dir("/proc/self/task")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+/// Switch every thread of the process, the new ones included.
+fn switch_threads() -> Result<(), anyhow::Error> {
+    let own = unsafe { libc::syscall(libc::SYS_gettid) } as libc::pid_t;
+    let pid = unsafe { libc::getpid() };
+
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as usize;
+    action.sa_flags = libc::SA_RESTART;
+    let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sigaction(signal(), &action, &mut previous) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    ERRNO.store(0, Ordering::SeqCst);
+    let mut result = Ok(());
+    // Our own thread last, it needs its privileges to signal the others.
+    let mut switched = HashSet::from([own]);
+    'threads: loop {
+        // Threads may be created meanwhile by those not switched yet.
+        let tids: Vec<_> = match thread_ids() {
+            Ok(tids) => tids
+                .into_iter()
+                .filter(|tid| !switched.contains(tid))
+                .collect(),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        if tids.is_empty() {
+            break;
+        }
+        for tid in tids {
+            switched.insert(tid);
+            SWITCHED.store(0, Ordering::SeqCst);
+            if unsafe { libc::syscall(libc::SYS_tgkill, pid, tid, signal()) } != 0 {
+                // It exited in the meantime.
+                continue;
+            }
+            let start = Instant::now();
+            while SWITCHED.load(Ordering::SeqCst) == 0 {
+                if start.elapsed() > Duration::from_secs(1) {
+                    result = Err(anyhow::anyhow!("thread {} didn't switch", tid));
+                    break 'threads;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+    if result.is_ok() {
+        if let Err(e) = unsafe { switch_thread() } {
+            let _ = ERRNO.compare_exchange(0, e, Ordering::SeqCst, Ordering::SeqCst);
+        }
+    }
+    unsafe { libc::sigaction(signal(), &previous, std::ptr::null_mut()) };
+
+    result?;
+    match ERRNO.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        e => Err(std::io::Error::from_raw_os_error(e).into()),
+    }
+}
+
+/// Switch the process to the user of Config::drop_privileges, if any.
+pub(crate) fn drop(config: &Config) -> Result<(), anyhow::Error> {
+    let Some(DropPrivileges {
+        user: name,
+        group: group_name,
+        seccomp: with_seccomp,
+    }) = &config.drop_privileges
+    else {
+        return Ok(());
+    };
+
+    let (uid, mut gid) = user(name)?;
+    if let Some(group_name) = group_name {
+        gid = group(group_name)?;
+    }
+    // A capability missing from the permitted set can't be kept.
+    let caps = needed(config) & permitted()?;
+
+    let _lock = LOCK.lock().unwrap();
+    UID.store(uid, Ordering::SeqCst);
+    GID.store(gid, Ordering::SeqCst);
+    CAPS.store(caps, Ordering::SeqCst);
+    switch_threads()?;
+    info!(
+        "Running as uid {} gid {} with the capabilities {:#x}",
+        uid, gid, caps
+    );
+
+    if *with_seccomp {
+        seccomp::install()?;
+        info!("seccomp filter installed");
+    }
+
+    Ok(())
+}
+
+/// A filter denying the syscalls the monitor never needs once started.
+mod seccomp {
+    use std::io;
+
+    const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+    /// Apply the filter to all the threads.
+    const SECCOMP_FILTER_FLAG_TSYNC: libc::c_ulong = 1;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JEQ_K: u16 = 0x15;
+    const BPF_JGE_K: u16 = 0x35;
+    const BPF_RET_K: u16 = 0x06;
+
+    /// Offsets in seccomp_data.
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    /// The x32 syscalls would bypass the filter on x86_64.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_setuid,
+        libc::SYS_setresuid,
+        libc::SYS_setgid,
+        libc::SYS_setresgid,
+        libc::SYS_setgroups,
+    ];
+
+    fn statement(code: u16, k: u32) -> libc::sock_filter {
+        jump(code, k, 0, 0)
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code, jt, jf, k }
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub(super) fn install() -> Result<(), anyhow::Error> {
+        let mut filter = vec![
+            statement(BPF_LD_W_ABS, ARCH),
+            jump(BPF_JEQ_K, AUDIT_ARCH, 1, 0),
+            statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+            statement(BPF_LD_W_ABS, NR),
+        ];
+        if cfg!(target_arch = "x86_64") {
+            filter.push(jump(BPF_JGE_K, X32_SYSCALL_BIT, 0, 1));
+            filter.push(statement(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32));
+        }
+        for (i, nr) in DENIED.iter().enumerate() {
+            // Jump to the EPERM after the remaining comparisons and the ALLOW.
+            let remaining = (DENIED.len() - i - 1) as u8;
+            filter.push(jump(BPF_JEQ_K, *nr as u32, remaining + 1, 0));
+        }
+        filter.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+        filter.push(statement(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32));
+
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::syscall(
+                    libc::SYS_seccomp,
+                    SECCOMP_SET_MODE_FILTER,
+                    SECCOMP_FILTER_FLAG_TSYNC,
+                    &program,
+                ) != 0
+            {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub(super) fn install() -> Result<(), anyhow::Error> {
+        anyhow::bail!("seccomp is only supported on x86_64 and aarch64")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has(caps: u64, cap: u32) -> bool {
+        caps & (1 << cap) != 0
+    }
+
+    #[test]
+    fn keeps_what_the_backend_reads_with() {
+        let caps = needed(&Config::default());
+        if cfg!(feature = "userspace") {
+            assert!(has(caps, preflight::CAP_NET_RAW));
+        }
+        #[cfg(not(feature = "userspace"))]
+        assert!(has(caps, preflight::CAP_BPF) || has(caps, preflight::CAP_SYS_ADMIN));
+    }
+
+    #[test]
+    fn keeps_sys_admin_for_the_netns() {
+        let config = Config {
+            netns: Some(crate::Netns::Named(String::from("test"))),
+            ..Default::default()
+        };
+        assert!(has(needed(&config), preflight::CAP_SYS_ADMIN));
+    }
+
+    #[cfg(not(feature = "userspace"))]
+    #[test]
+    fn observers_dont_attach() {
+        let monitor = needed(&Config::default());
+        let observer = needed(&Config {
+            observer: true,
+            ..Default::default()
+        });
+        assert!(has(monitor, preflight::CAP_NET_ADMIN));
+        assert!(!has(observer, preflight::CAP_NET_ADMIN));
+        assert!(has(observer, preflight::CAP_BPF) || has(observer, preflight::CAP_SYS_ADMIN));
+    }
+}
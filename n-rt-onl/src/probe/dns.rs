@@ -0,0 +1,154 @@
+use std::{
+    fs, io,
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant},
+};
+
+use pnet::datalink::NetworkInterface;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::netns;
+
+static QUERY_ID: AtomicU16 = AtomicU16::new(0);
+
+/// Return the first nameserver of /etc/resolv.conf.
+pub(crate) fn system_resolver() -> Option<SocketAddr> {
+    let resolv = fs::read_to_string("/etc/resolv.conf").ok()?;
+    resolv.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("nameserver") {
+            return None;
+        }
+        // Drop the zone of link-local addresses, which IpAddr can't parse.
+        let ip: IpAddr = words.next()?.split('%').next()?.parse().ok()?;
+        Some(SocketAddr::new(ip, 53))
+    })
+}
+
+/// Ask the resolver for the NS records of the root zone, which it always has
+/// at hand, through the interface if any, and wait up to `timeout` for a
+/// successful answer.
+/// Returns the time it took to answer, None if it didn't (or failed) in time.
+pub(crate) async fn query(
+    resolver: SocketAddr,
+    interface: Option<&NetworkInterface>,
+    timeout: Duration,
+) -> io::Result<Option<Duration>> {
+    let socket = netns::run(|| {
+        Socket::new(
+            Domain::for_address(resolver),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )
+    })?;
+    if let Some(interface) = interface {
+        super::bind(&socket, interface, resolver.ip())?;
+    }
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.connect(resolver).await?;
+
+    let id = std::process::id() as u16 ^ QUERY_ID.fetch_add(1, Ordering::Relaxed);
+    let mut query = Vec::with_capacity(17);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    // Root name, type NS, class IN.
+    query.extend_from_slice(&[0, 0, 2, 0, 1]);
+
+    let start = Instant::now();
+    socket.send(&query).await?;
+
+    let mut buf = [0u8; 512];
+    let wait = async {
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            // Same id, is a response, and its rcode is NOERROR.
+            let response = &buf[..len];
+            if len < 12 || response[..2] != id.to_be_bytes() || response[2] & 0x80 == 0 {
+                continue;
+            }
+            return Ok::<_, io::Error>((response[3] & 0x0f == 0).then(|| start.elapsed()));
+        }
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(Ok(rtt)) => Ok(rtt),
+        // The resolver's host told us nothing listens there.
+        Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(None),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    /// Answer the first query on a local port with the header `reply`
+    /// gives from the query's, returning the resolver's address.
+    async fn resolver(reply: impl FnOnce(&mut [u8]) + Send + 'static) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            let response = &mut buf[..len];
+            // A response, with no answer (not looked at).
+            response[2] |= 0x80;
+            reply(response);
+            socket.send_to(response, from).await.unwrap();
+            // Don't close it, the query would be refused.
+            std::future::pending::<()>().await;
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn answered() {
+        let resolver = resolver(|_| {}).await;
+        assert!(query(resolver, None, TIMEOUT).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn ignores_the_other_ids() {
+        let resolver = resolver(|response| response[1] ^= 0xff).await;
+        assert_eq!(query(resolver, None, TIMEOUT).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn fails_with_an_error_rcode() {
+        // SERVFAIL, answered well before the timeout.
+        let resolver = resolver(|response| response[3] |= 2).await;
+        let start = Instant::now();
+        assert_eq!(query(resolver, None, TIMEOUT * 10).await.unwrap(), None);
+        assert!(start.elapsed() < TIMEOUT * 10);
+    }
+
+    #[tokio::test]
+    async fn refused() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = socket.local_addr().unwrap();
+        drop(socket);
+
+        let start = Instant::now();
+        assert_eq!(query(resolver, None, TIMEOUT * 10).await.unwrap(), None);
+        assert!(start.elapsed() < TIMEOUT * 10);
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        // Never answers.
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = socket.local_addr().unwrap();
+
+        let start = Instant::now();
+        assert_eq!(query(resolver, None, TIMEOUT).await.unwrap(), None);
+        assert!(start.elapsed() >= TIMEOUT);
+    }
+}
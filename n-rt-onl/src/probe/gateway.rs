@@ -0,0 +1,89 @@
+use std::{collections::VecDeque, net::IpAddr, time::Duration};
+
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+use crate::{common, probe::neighbor, GatewayStatus, LinkStatus};
+
+/// Number of probes the latency and the loss are computed on.
+const WINDOW: usize = 10;
+/// Number of probes in a row which must go unanswered to be unreachable.
+const UNREACHABLE_AFTER: usize = 3;
+
+fn status(address: IpAddr, window: &VecDeque<Option<Duration>>) -> GatewayStatus {
+    let answered: Vec<Duration> = window.iter().flatten().copied().collect();
+
+    GatewayStatus {
+        address,
+        reachable: window
+            .iter()
+            .rev()
+            .take(UNREACHABLE_AFTER)
+            .any(Option::is_some),
+        latency: (!answered.is_empty())
+            .then(|| answered.iter().sum::<Duration>() / answered.len() as u32),
+        loss: (window.len() - answered.len()) as f32 / window.len() as f32,
+    }
+}
+
+/// Probe the gateway of the interface every `interval`, the receivers
+/// are only notified when it changes or its reachability does.
+pub(crate) fn watch_gateway(
+    iface: watch::Receiver<String>,
+    link: Option<watch::Receiver<LinkStatus>>,
+    gateway: Option<IpAddr>,
+    interval: Duration,
+) -> watch::Receiver<Option<GatewayStatus>> {
+    let (status_tx, status_rx) = watch::channel(None::<GatewayStatus>);
+
+    tokio::spawn(async move {
+        let mut window = VecDeque::with_capacity(WINDOW);
+        let mut tick = tokio::time::interval(interval);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+
+            let address = gateway.or_else(|| link.as_ref().and_then(|l| l.borrow().gateway));
+            let interface = common::find_interface(&iface.borrow());
+            let (Some(address), Some(interface)) = (address, interface) else {
+                window.clear();
+                status_tx.send_if_modified(|status| status.take().is_some());
+                continue;
+            };
+            if status_tx
+                .borrow()
+                .as_ref()
+                .is_some_and(|s| s.address != address)
+            {
+                window.clear();
+            }
+
+            let probe = move || neighbor::resolve(&interface, address, interval);
+            let rtt = match tokio::task::spawn_blocking(probe).await {
+                Ok(Ok(rtt)) => rtt,
+                Ok(Err(e)) => {
+                    debug!("cannot probe gateway({}): {}", address, e);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            if window.len() == WINDOW {
+                window.pop_front();
+            }
+            window.push_back(rtt);
+
+            // Latency and loss are updated silently.
+            let new = status(address, &window);
+            status_tx.send_if_modified(|status| {
+                let changed = status.as_ref().map(|s| (s.address, s.reachable))
+                    != Some((new.address, new.reachable));
+                if changed {
+                    info!("gateway({}) is now {:?}", address, new);
+                }
+                *status = Some(new);
+                changed
+            });
+        }
+    });
+
+    status_rx
+}
@@ -0,0 +1,226 @@
+use std::{
+    io,
+    net::ToSocketAddrs,
+    time::{Duration, Instant},
+};
+
+use pnet::datalink::NetworkInterface;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::tcp;
+use crate::netns;
+
+/// We only look at the beginning of the responses.
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+/// Response to a GET.
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub status: u16,
+    /// Location header, where we're redirected to.
+    pub location: Option<String>,
+    /// Body, possibly truncated.
+    pub body: Vec<u8>,
+}
+
+/// Split a http:// URL into its host, port and path.
+fn split_url(url: &str) -> io::Result<(&str, u16, &str)> {
+    let invalid = |what| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", what, url));
+
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// URLs are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    // The port comes after the brackets of IPv6 addresses.
+    let (host, port) = match authority.rfind(':') {
+        Some(i) if !authority[i..].contains(']') => (
+            &authority[..i],
+            authority[i + 1..]
+                .parse()
+                .map_err(|_| invalid("invalid port"))?,
+        ),
+        _ => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(invalid("no host"));
+    }
+
+    Ok((host, port, path))
+}
+
+fn parse_response(raw: &[u8]) -> io::Result<Response> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response");
+
+    let end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(invalid)?;
+    let head = std::str::from_utf8(&raw[..end]).map_err(|_| invalid())?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid)?;
+    let location = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("location")
+            .then(|| value.trim().to_owned())
+    });
+
+    Ok(Response {
+        status,
+        location,
+        body: raw[end + 4..].to_vec(),
+    })
+}
+
+/// GET the http:// `url` through the interface if any,
+/// and wait up to `timeout` for the whole response.
+/// Returns the time it took along with the response, None if it didn't in time.
+pub(crate) async fn get(
+    url: &str,
+    interface: Option<&NetworkInterface>,
+    timeout: Duration,
+) -> io::Result<Option<(Duration, Response)>> {
+    let (host, port, path) = split_url(url)?;
+    let start = Instant::now();
+
+    let fetch = async {
+        // getaddrinfo reads the resolv.conf of the process (its mount namespace),
+        // only its queries leave from the namespace of the interface.
+        let name = host.trim_matches(['[', ']']).to_owned();
+        let addr = tokio::task::spawn_blocking(move || {
+            netns::run(|| (name.as_str(), port).to_socket_addrs())
+        })
+        .await??
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))?;
+        let mut stream = tcp::connect(addr, interface).await?;
+        let host_header = match port {
+            80 => host.to_owned(),
+            port => format!("{}:{}", host, port),
+        };
+        // HTTP/1.0 so the server closes the connection and doesn't chunk the body.
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: n-rt-onl\r\nConnection: close\r\n\r\n",
+            path, host_header
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut raw = Vec::new();
+        stream.take(MAX_RESPONSE).read_to_end(&mut raw).await?;
+        parse_response(&raw)
+    };
+
+    match tokio::time::timeout(timeout, fetch).await {
+        Ok(response) => Ok(Some((start.elapsed(), response?))),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Serve `response` once on a local port, returning the URL to GET.
+    /// Without a response, the connection is kept open without answering.
+    pub(crate) async fn serve(response: Option<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/check", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            match response {
+                Some(response) => _ = stream.write_all(response.as_bytes()).await,
+                None => std::future::pending().await,
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn splits_the_urls() {
+        let split = |url| split_url(url).unwrap();
+        assert_eq!(split("http://example.com"), ("example.com", 80, "/"));
+        assert_eq!(
+            split("http://example.com:8080/generate_204"),
+            ("example.com", 8080, "/generate_204")
+        );
+        assert_eq!(split("http://[2001:db8::1]/a"), ("[2001:db8::1]", 80, "/a"));
+        assert_eq!(split("http://[2001:db8::1]:81"), ("[2001:db8::1]", 81, "/"));
+    }
+
+    #[test]
+    fn rejects_the_invalid_urls() {
+        for url in [
+            "https://example.com",
+            "example.com",
+            "http://:80/",
+            "http://a:b/",
+        ] {
+            let e = split_url(url).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", url);
+        }
+    }
+
+    #[test]
+    fn parses_the_responses() {
+        let response =
+            parse_response(b"HTTP/1.1 302 Found\r\nlocation: http://portal/\r\n\r\nbody").unwrap();
+        assert_eq!(response.status, 302);
+        assert_eq!(response.location.as_deref(), Some("http://portal/"));
+        assert_eq!(response.body, b"body");
+
+        let response = parse_response(b"HTTP/1.0 204 No Content\r\n\r\n").unwrap();
+        assert_eq!(response.status, 204);
+        assert_eq!(response.location, None);
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn rejects_the_invalid_responses() {
+        for raw in [
+            &b"HTTP/1.1 200 OK\r\n"[..],
+            b"HTTP/1.1 OK\r\n\r\n",
+            b"\xff\r\n\r\n",
+        ] {
+            let e = parse_response(raw).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_the_response() {
+        let url = serve(Some("HTTP/1.0 200 OK\r\n\r\nhello")).await;
+        let (_, response) = get(&url, None, TIMEOUT).await.unwrap().unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[tokio::test]
+    async fn refused() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let e = get(&url, None, TIMEOUT).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let url = serve(None).await;
+        let timeout = Duration::from_millis(100);
+        assert!(get(&url, None, timeout).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant},
+};
+
+use pnet::{
+    datalink::NetworkInterface,
+    packet::{
+        icmp::{self, echo_request::MutableEchoRequestPacket, IcmpPacket, IcmpType, IcmpTypes},
+        icmpv6::Icmpv6Types,
+        ipv4::Ipv4Packet,
+        Packet,
+    },
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::netns;
+
+/// Length of the IPv6 header, we don't expect extension headers in ICMPv6 errors.
+const IPV6_HEADER_LEN: usize = 40;
+
+static SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+/// Answer to an echo request.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Reply {
+    /// The target answered after the given round-trip time.
+    Echo(Duration),
+    /// The request ran out of hops at the given router.
+    TimeExceeded(IpAddr),
+}
+
+/// Identifier of our echo requests, to tell our replies from the others.
+fn identifier() -> u16 {
+    std::process::id() as u16
+}
+
+fn echo_request(target: IpAddr, seq: u16) -> Vec<u8> {
+    let mut buf = vec![0u8; MutableEchoRequestPacket::minimum_packet_size() + 8];
+    let mut request = MutableEchoRequestPacket::new(&mut buf).unwrap();
+    request.set_identifier(identifier());
+    request.set_sequence_number(seq);
+    match target {
+        IpAddr::V4(_) => {
+            request.set_icmp_type(IcmpTypes::EchoRequest);
+            let checksum = icmp::checksum(&IcmpPacket::new(request.packet()).unwrap());
+            request.set_checksum(checksum);
+        }
+        // The kernel computes the checksum of ICMPv6 raw sockets.
+        IpAddr::V6(_) => request.set_icmp_type(IcmpType(Icmpv6Types::EchoRequest.0)),
+    }
+
+    buf
+}
+
+/// Return the (type, identifier, sequence) of an echo request or reply.
+fn echo_header(icmp: &[u8]) -> Option<(u8, u16, u16)> {
+    let header = icmp.get(..8)?;
+    Some((
+        header[0],
+        u16::from_be_bytes([header[4], header[5]]),
+        u16::from_be_bytes([header[6], header[7]]),
+    ))
+}
+
+/// Return the ICMP message of an IPv4 packet.
+fn ipv4_payload(packet: &[u8]) -> Option<&[u8]> {
+    let header_len = Ipv4Packet::new(packet)?.get_header_length() as usize * 4;
+    packet.get(header_len..)
+}
+
+/// Return the reply if the message received `from` answers our request `seq`.
+/// The kernel picks the identifier of unprivileged sockets and only gives
+/// them their replies, while raw IPv4 sockets also return the IP header.
+fn reply(
+    target: IpAddr,
+    raw: bool,
+    message: &[u8],
+    seq: u16,
+    from: IpAddr,
+    rtt: Duration,
+) -> Option<Reply> {
+    let ours = |icmp: &[u8], kind: u8| match echo_header(icmp) {
+        Some((k, _, s)) if !raw => (k, s) == (kind, seq),
+        header => header == Some((kind, identifier(), seq)),
+    };
+    let (icmp, echo_reply, time_exceeded, echo_request, quoted) = match target {
+        IpAddr::V4(_) => {
+            // Only Linux strips the IP header for unprivileged sockets.
+            let icmp = match message.first() {
+                Some(b) if b >> 4 == 4 => ipv4_payload(message)?,
+                _ => message,
+            };
+            let quoted = ipv4_payload(icmp.get(8..)?);
+            (icmp, 0, 11, 8, quoted)
+        }
+        IpAddr::V6(_) => {
+            let quoted = message.get(8 + IPV6_HEADER_LEN..);
+            (message, 129, 3, 128, quoted)
+        }
+    };
+
+    match *icmp.first()? {
+        kind if kind == echo_reply && ours(icmp, echo_reply) => Some(Reply::Echo(rtt)),
+        kind if kind == time_exceeded && ours(quoted?, echo_request) => {
+            Some(Reply::TimeExceeded(from))
+        }
+        _ => None,
+    }
+}
+
+/// Open an unprivileged ICMP socket if allowed (net.ipv4.ping_group_range
+/// on Linux), or a raw one which needs CAP_NET_RAW. The errors on the way
+/// (like TimeExceeded) are only given to raw sockets, so use one for `hops`.
+/// Returns the socket and whether it's a raw one.
+fn open(
+    target: IpAddr,
+    hops: Option<u8>,
+    interface: Option<&NetworkInterface>,
+) -> io::Result<(Socket, bool)> {
+    let (domain, protocol) = match target {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+    };
+    let unprivileged = match hops {
+        Some(_) => None,
+        None => netns::run(|| Socket::new(domain, Type::DGRAM, Some(protocol))).ok(),
+    };
+    let (socket, raw) = match unprivileged {
+        Some(socket) => (socket, false),
+        None => (
+            netns::run(|| Socket::new(domain, Type::RAW, Some(protocol)))?,
+            true,
+        ),
+    };
+    if let Some(interface) = interface {
+        super::bind(&socket, interface, target)?;
+    }
+    match (target, hops) {
+        (IpAddr::V4(_), Some(hops)) => socket.set_ttl_v4(hops.into())?,
+        (IpAddr::V6(_), Some(hops)) => socket.set_unicast_hops_v6(hops.into())?,
+        (_, None) => {}
+    }
+
+    Ok((socket, raw))
+}
+
+/// Send an echo request to `target`, limited to `hops` if any, through the
+/// interface if any, and wait up to `timeout` for its reply.
+/// Returns None if nothing answered in time.
+pub(crate) async fn ping(
+    target: IpAddr,
+    hops: Option<u8>,
+    interface: Option<&NetworkInterface>,
+    timeout: Duration,
+) -> io::Result<Option<Reply>> {
+    let (socket, raw) = open(target, hops, interface)?;
+    socket.set_nonblocking(true)?;
+    // ICMP sockets are datagram ones as far as sendto/recvfrom are concerned.
+    let socket = UdpSocket::from_std(socket.into())?;
+
+    let seq = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let start = Instant::now();
+    socket
+        .send_to(&echo_request(target, seq), SocketAddr::new(target, 0))
+        .await?;
+
+    let mut buf = [0u8; 1500];
+    let wait = async {
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            let rtt = start.elapsed();
+            if let Some(reply) = reply(target, raw, &buf[..len], seq, from.ip(), rtt) {
+                return Ok(reply);
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(reply) => reply.map(Some),
+        Err(_) => Ok(None),
+    }
+}
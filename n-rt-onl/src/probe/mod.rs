@@ -0,0 +1,52 @@
+//! Probes used to find where an outage is, to watch the gateway
+//! and to check the connectivity in the background.
+//! The neighbor ones are blocking and meant to be run with spawn_blocking.
+
+pub(crate) mod dns;
+pub(crate) mod gateway;
+pub(crate) mod http;
+pub(crate) mod icmp;
+pub(crate) mod neighbor;
+pub(crate) mod portal;
+pub(crate) mod scheduler;
+pub(crate) mod tcp;
+
+use std::{io, net::IpAddr};
+
+use pnet::datalink::NetworkInterface;
+use socket2::Socket;
+
+/// Make the probe to `target` leave through the interface whatever the
+/// routing table says: SO_BINDTODEVICE on Linux, IP_BOUND_IF on macOS,
+/// and elsewhere the selection of an address of the interface as source.
+pub(crate) fn bind(
+    socket: &Socket,
+    interface: &NetworkInterface,
+    target: IpAddr,
+) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let _ = target;
+        socket.bind_device(Some(interface.name.as_bytes()))
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let index = std::num::NonZeroU32::new(interface.index);
+        match target {
+            IpAddr::V4(_) => socket.bind_device_by_index_v4(index),
+            IpAddr::V6(_) => socket.bind_device_by_index_v6(index),
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let source = interface
+            .ips
+            .iter()
+            .map(|ip| ip.ip())
+            .find(|ip| ip.is_ipv4() == target.is_ipv4())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address to bind to"))?;
+        socket.bind(&std::net::SocketAddr::new(source, 0).into())
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use pnet::{
+    datalink::{self, Channel::Ethernet, NetworkInterface},
+    packet::{
+        arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket},
+        ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+        icmpv6::{
+            self,
+            ndp::{
+                MutableNeighborSolicitPacket, NdpOption, NdpOptionTypes, NeighborAdvertPacket,
+                NeighborSolicitPacket,
+            },
+            Icmpv6Packet, Icmpv6Types,
+        },
+        ip::IpNextHeaderProtocols,
+        ipv6::{Ipv6Packet, MutableIpv6Packet},
+        MutablePacket, Packet,
+    },
+    util::MacAddr,
+};
+
+use crate::netns;
+
+/// Build an ARP request for `target` from the interface.
+fn arp_request(interface: &NetworkInterface, mac: MacAddr, target: Ipv4Addr) -> Vec<u8> {
+    // Without an IPv4 address, send an ARP probe (RFC 5227) from 0.0.0.0.
+    let source = interface
+        .ips
+        .iter()
+        .find_map(|ip| match ip.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .unwrap_or(Ipv4Addr::UNSPECIFIED);
+
+    let mut buf =
+        vec![0u8; EthernetPacket::minimum_packet_size() + ArpPacket::minimum_packet_size()];
+    let mut ethernet = MutableEthernetPacket::new(&mut buf).unwrap();
+    ethernet.set_destination(MacAddr::broadcast());
+    ethernet.set_source(mac);
+    ethernet.set_ethertype(EtherTypes::Arp);
+
+    let mut arp = MutableArpPacket::new(ethernet.payload_mut()).unwrap();
+    arp.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp.set_protocol_type(EtherTypes::Ipv4);
+    arp.set_hw_addr_len(6);
+    arp.set_proto_addr_len(4);
+    arp.set_operation(ArpOperations::Request);
+    arp.set_sender_hw_addr(mac);
+    arp.set_sender_proto_addr(source);
+    arp.set_target_hw_addr(MacAddr::zero());
+    arp.set_target_proto_addr(target);
+
+    buf
+}
+
+/// Build a Neighbor Solicitation for `target` from the link-local address of the interface.
+fn neighbor_solicit(
+    interface: &NetworkInterface,
+    mac: MacAddr,
+    target: Ipv6Addr,
+) -> io::Result<Vec<u8>> {
+    let source = interface
+        .ips
+        .iter()
+        .find_map(|ip| match ip.ip() {
+            IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => Some(ip),
+            _ => None,
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no link-local address"))?;
+    // Solicited-node multicast address of the target, and its MAC.
+    let t = target.octets();
+    let destination = Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | t[13] as u16,
+        u16::from_be_bytes([t[14], t[15]]),
+    );
+    let destination_mac = MacAddr::new(0x33, 0x33, 0xff, t[13], t[14], t[15]);
+
+    // The Neighbor Solicitation with a source link-layer address option.
+    let solicit_len = NeighborSolicitPacket::minimum_packet_size() + 8;
+    let mut buf =
+        vec![
+            0u8;
+            EthernetPacket::minimum_packet_size() + Ipv6Packet::minimum_packet_size() + solicit_len
+        ];
+    let mut ethernet = MutableEthernetPacket::new(&mut buf).unwrap();
+    ethernet.set_destination(destination_mac);
+    ethernet.set_source(mac);
+    ethernet.set_ethertype(EtherTypes::Ipv6);
+
+    let mut ipv6 = MutableIpv6Packet::new(ethernet.payload_mut()).unwrap();
+    ipv6.set_version(6);
+    ipv6.set_payload_length(solicit_len as u16);
+    ipv6.set_next_header(IpNextHeaderProtocols::Icmpv6);
+    ipv6.set_hop_limit(255);
+    ipv6.set_source(source);
+    ipv6.set_destination(destination);
+
+    let mut solicit = MutableNeighborSolicitPacket::new(ipv6.payload_mut()).unwrap();
+    solicit.set_icmpv6_type(Icmpv6Types::NeighborSolicit);
+    solicit.set_target_addr(target);
+    solicit.set_options(&[NdpOption {
+        option_type: NdpOptionTypes::SourceLLAddr,
+        length: 1,
+        data: mac.octets().to_vec(),
+    }]);
+    let checksum = icmpv6::checksum(
+        &Icmpv6Packet::new(solicit.packet()).unwrap(),
+        &source,
+        &destination,
+    );
+    solicit.set_checksum(checksum);
+
+    Ok(buf)
+}
+
+/// Whether the frame is the ARP reply or Neighbor Advertisement of `target`.
+fn is_reply(frame: &[u8], target: IpAddr) -> bool {
+    let Some(ethernet) = EthernetPacket::new(frame) else {
+        return false;
+    };
+
+    match (ethernet.get_ethertype(), target) {
+        (EtherTypes::Arp, IpAddr::V4(target)) => {
+            ArpPacket::new(ethernet.payload()).is_some_and(|arp| {
+                arp.get_operation() == ArpOperations::Reply && arp.get_sender_proto_addr() == target
+            })
+        }
+        (EtherTypes::Ipv6, IpAddr::V6(target)) => {
+            let Some(ipv6) = Ipv6Packet::new(ethernet.payload()) else {
+                return false;
+            };
+            ipv6.get_next_header() == IpNextHeaderProtocols::Icmpv6
+                && NeighborAdvertPacket::new(ipv6.payload()).is_some_and(|advert| {
+                    advert.get_icmpv6_type() == Icmpv6Types::NeighborAdvert
+                        && advert.get_target_addr() == target
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Send an ARP request (IPv4) or a Neighbor Solicitation (IPv6) for `target`
+/// on the interface and wait up to `timeout` for its reply.
+/// Returns the time it took to answer, None if it didn't in time.
+pub(crate) fn resolve(
+    interface: &NetworkInterface,
+    target: IpAddr,
+    timeout: Duration,
+) -> Result<Option<Duration>, anyhow::Error> {
+    let Some(mac) = interface.mac else {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "no link-layer address").into());
+    };
+    let request = match target {
+        IpAddr::V4(target) => arp_request(interface, mac, target),
+        IpAddr::V6(target) => neighbor_solicit(interface, mac, target)?,
+    };
+
+    let config = datalink::Config {
+        read_timeout: Some(timeout),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = match netns::run(|| datalink::channel(interface, config))? {
+        Ethernet(tx, rx) => (tx, rx),
+        _ => unreachable!("channel type not supported"),
+    };
+
+    let start = Instant::now();
+    if let Some(sent) = tx.send_to(&request, None) {
+        sent?;
+    }
+
+    while start.elapsed() < timeout {
+        match rx.next() {
+            Ok(frame) if is_reply(frame, target) => return Ok(Some(start.elapsed())),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,120 @@
+use tokio::{sync::watch, time::MissedTickBehavior};
+
+use crate::{common, probe::http, PortalCheck};
+
+/// Whether the response to the check was intercepted,
+/// None if we didn't get a conclusive response.
+async fn intercepted(check: &PortalCheck, iface: Option<&watch::Receiver<String>>) -> Option<bool> {
+    let interface = match iface {
+        Some(iface) => Some(common::find_interface(&iface.borrow())?),
+        None => None,
+    };
+    let response = match http::get(&check.url, interface.as_ref(), check.timeout).await {
+        Ok(Some((_, response))) => response,
+        Ok(None) => {
+            debug!("portal check: {} didn't answer", check.url);
+            return None;
+        }
+        Err(e) => {
+            debug!("portal check: cannot GET {}: {}", check.url, e);
+            return None;
+        }
+    };
+
+    let expected = check.status == response.status
+        && check
+            .body
+            .as_ref()
+            .is_none_or(|body| response.body == body.as_bytes());
+    let redirected = (300..400).contains(&response.status) && response.location.is_some();
+    if expected {
+        Some(false)
+    } else if redirected || response.status == 200 {
+        debug!(
+            "portal check: got {} instead of {} (location: {:?})",
+            response.status, check.status, response.location
+        );
+        Some(true)
+    } else {
+        // E.g. the check server is broken, that doesn't tell anything.
+        debug!("portal check: {} answered {}", check.url, response.status);
+        None
+    }
+}
+
+/// Run the check every interval through the interface `iface` if any,
+/// the receivers are notified when the response starts or stops being
+/// intercepted. The last verdict is kept while we don't get a response.
+pub(crate) fn watch_portal(
+    iface: Option<watch::Receiver<String>>,
+    check: PortalCheck,
+) -> watch::Receiver<bool> {
+    let (captive_tx, captive_rx) = watch::channel(false);
+
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(check.interval);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+
+            let Some(captive) = intercepted(&check, iface.as_ref()).await else {
+                continue;
+            };
+            captive_tx.send_if_modified(|current| {
+                if *current == captive {
+                    return false;
+                }
+                if captive {
+                    info!("The connectivity check is intercepted");
+                } else {
+                    info!("The connectivity check isn't intercepted anymore");
+                }
+                *current = captive;
+                true
+            });
+        }
+    });
+
+    captive_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    async fn check(response: &'static str) -> Option<bool> {
+        let check = PortalCheck {
+            url: http::tests::serve(Some(response)).await,
+            timeout: Duration::from_secs(2),
+            ..PortalCheck::default()
+        };
+        intercepted(&check, None).await
+    }
+
+    #[tokio::test]
+    async fn open() {
+        assert_eq!(check("HTTP/1.1 204 No Content\r\n\r\n").await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn redirected() {
+        let response = "HTTP/1.1 302 Found\r\nLocation: http://portal/login\r\n\r\n";
+        assert_eq!(check(response).await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn altered() {
+        let response = "HTTP/1.1 200 OK\r\n\r\n<html>Login</html>";
+        assert_eq!(check(response).await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn broken_server() {
+        assert_eq!(check("HTTP/1.1 500 Oops\r\n\r\n").await, None);
+        assert_eq!(check("HTTP/1.1 404 Not Found\r\n\r\n").await, None);
+        // A redirect goes somewhere.
+        assert_eq!(check("HTTP/1.1 302 Found\r\n\r\n").await, None);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::{BuildHasher, Hasher},
+    io,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use pnet::datalink::NetworkInterface;
+use tokio::sync::{broadcast, watch};
+
+use crate::{
+    common,
+    probe::{dns, http, icmp, tcp},
+    ProbeKind, ProbeResult, ProbeTarget, Quorum,
+};
+
+/// Number of outcomes kept per target.
+const KEPT_OUTCOMES: usize = 64;
+
+/// Latest (time, success) of a target.
+type History = VecDeque<(Instant, bool)>;
+
+/// Latest outcomes of each target, shared with the analysis.
+#[derive(Debug, Clone)]
+pub(crate) struct Outcomes {
+    history: Arc<Mutex<Vec<History>>>,
+    /// Weight of each target in the quorum.
+    weights: Arc<Vec<u32>>,
+    /// When the last successful probe the backends account for was
+    /// sent, in the clock of their timestamps.
+    last_sent: Arc<AtomicU64>,
+}
+
+impl Outcomes {
+    fn record(&self, target: usize, success: bool) {
+        let mut outcomes = self.history.lock().unwrap();
+        let outcomes = &mut outcomes[target];
+        if outcomes.len() == KEPT_OUTCOMES {
+            outcomes.pop_front();
+        }
+        outcomes.push_back((Instant::now(), success));
+    }
+
+    /// Whether the targets which succeeded, and the ones which failed,
+    /// during the window of the quorum weigh enough to be Up or Down.
+    pub(crate) fn quorum(&self, quorum: &Quorum) -> (bool, bool) {
+        let outcomes = self.history.lock().unwrap();
+        let (mut up, mut down) = (0, 0);
+        for (history, weight) in outcomes.iter().zip(self.weights.iter()) {
+            let mut recent = history
+                .iter()
+                .filter(|(at, _)| at.elapsed() <= quorum.window)
+                .map(|(_, success)| *success)
+                .peekable();
+            if recent.peek().is_none() {
+                continue;
+            }
+            if recent.any(|success| success) {
+                up += weight;
+            } else {
+                down += weight;
+            }
+        }
+
+        (up >= quorum.up, down >= quorum.down)
+    }
+
+    /// When the last successful probe the backends account for was sent,
+    /// 0 if none was.
+    pub(crate) fn last_sent(&self) -> u64 {
+        self.last_sent.load(Ordering::SeqCst)
+    }
+}
+
+/// Whether the backends account for the traffic to `ip`: they only look at
+/// IPv4 and skip the private destinations (as we're likely private too).
+fn accounted(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !(ip.is_private() || ip.is_loopback() || ip.is_link_local()),
+        IpAddr::V6(_) => false,
+    }
+}
+
+/// Random duration up to `max`.
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return max;
+    }
+    // Each RandomState is seeded differently, no need for a rand dependency.
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+/// Run a probe through the interface if any,
+/// returning its round-trip time if it succeeded in time.
+pub(crate) async fn run(
+    kind: &ProbeKind,
+    interface: Option<&NetworkInterface>,
+    timeout: Duration,
+) -> io::Result<Option<Duration>> {
+    match kind {
+        ProbeKind::Icmp(ip) => Ok(match icmp::ping(*ip, None, interface, timeout).await? {
+            Some(icmp::Reply::Echo(rtt)) => Some(rtt),
+            _ => None,
+        }),
+        ProbeKind::Tcp(addr) => {
+            let start = Instant::now();
+            match tokio::time::timeout(timeout, tcp::connect(*addr, interface)).await {
+                Ok(stream) => stream.map(|_| Some(start.elapsed())),
+                Err(_) => Ok(None),
+            }
+        }
+        ProbeKind::Dns(resolver) => dns::query(*resolver, interface, timeout).await,
+        ProbeKind::Http(url) => Ok(match http::get(url, interface, timeout).await? {
+            Some((rtt, response)) if (200..400).contains(&response.status) => Some(rtt),
+            _ => None,
+        }),
+    }
+}
+
+/// Probe each target at its own pace through the interface `iface` if any,
+/// publishing the results. `now` gives the time in the clock of the backend.
+pub(crate) fn start_probes(
+    targets: Vec<ProbeTarget>,
+    iface: Option<watch::Receiver<String>>,
+    now: fn() -> u64,
+    results_tx: broadcast::Sender<ProbeResult>,
+) -> Outcomes {
+    let history = (0..targets.len())
+        .map(|_| VecDeque::with_capacity(KEPT_OUTCOMES))
+        .collect();
+    let outcomes = Outcomes {
+        history: Arc::new(Mutex::new(history)),
+        weights: Arc::new(targets.iter().map(|t| t.weight).collect()),
+        last_sent: Arc::default(),
+    };
+
+    for (i, target) in targets.into_iter().enumerate() {
+        let outcomes = outcomes.clone();
+        let iface = iface.clone();
+        let results_tx = results_tx.clone();
+        let address = match &target.kind {
+            ProbeKind::Icmp(ip) => Some(*ip),
+            ProbeKind::Tcp(addr) | ProbeKind::Dns(addr) => Some(addr.ip()),
+            ProbeKind::Http(_) => None,
+        };
+        tokio::spawn(async move {
+            loop {
+                let interface = match &iface {
+                    Some(iface) => common::find_interface(&iface.borrow()),
+                    None => None,
+                };
+                let rtt = match (&iface, &interface) {
+                    // The probes can't go through a missing interface.
+                    (Some(_), None) => None,
+                    _ => match run(&target.kind, interface.as_ref(), target.timeout).await {
+                        Ok(rtt) => rtt,
+                        Err(e) => {
+                            debug!("probe {:?} failed: {}", target.kind, e);
+                            None
+                        }
+                    },
+                };
+                trace!("probe {:?}: {:?}", target.kind, rtt);
+                outcomes.record(i, rtt.is_some());
+                if let (Some(rtt), Some(true)) = (rtt, address.map(accounted)) {
+                    let sent = now().saturating_sub(common::to_ns(rtt));
+                    outcomes.last_sent.store(sent, Ordering::SeqCst);
+                }
+                // Nobody may be listening.
+                _ = results_tx.send(ProbeResult {
+                    target: target.kind.clone(),
+                    rtt,
+                });
+
+                tokio::time::sleep(target.interval + jitter(target.jitter)).await;
+            }
+        });
+    }
+
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    fn outcomes(weights: &[u32]) -> Outcomes {
+        Outcomes {
+            history: Arc::new(Mutex::new(vec![VecDeque::new(); weights.len()])),
+            weights: Arc::new(weights.to_vec()),
+            last_sent: Arc::default(),
+        }
+    }
+
+    fn quorum(up: u32, down: u32) -> Quorum {
+        Quorum {
+            up,
+            down,
+            window: WINDOW,
+        }
+    }
+
+    #[test]
+    fn weighs_the_targets() {
+        let outcomes = outcomes(&[1, 2, 1]);
+        outcomes.record(0, true);
+        outcomes.record(1, false);
+        outcomes.record(2, false);
+
+        assert_eq!(outcomes.quorum(&quorum(1, 3)), (true, true));
+        assert_eq!(outcomes.quorum(&quorum(2, 4)), (false, false));
+        assert_eq!(outcomes.quorum(&quorum(2, 2)), (false, true));
+    }
+
+    #[test]
+    fn one_success_in_the_window_is_enough() {
+        let outcomes = outcomes(&[1]);
+        outcomes.record(0, false);
+        outcomes.record(0, true);
+        outcomes.record(0, false);
+
+        assert_eq!(outcomes.quorum(&quorum(1, 1)), (true, false));
+    }
+
+    #[test]
+    fn ignores_the_outcomes_out_of_the_window() {
+        let outcomes = outcomes(&[1, 1]);
+        let old = Instant::now().checked_sub(WINDOW * 2).unwrap();
+        outcomes.history.lock().unwrap()[0].push_back((old, true));
+        outcomes.record(1, false);
+
+        // The first target wasn't probed during the window: neither.
+        assert_eq!(outcomes.quorum(&quorum(1, 2)), (false, false));
+        assert_eq!(outcomes.quorum(&quorum(1, 1)), (false, true));
+    }
+
+    #[test]
+    fn not_probed_yet() {
+        assert_eq!(outcomes(&[1, 1]).quorum(&quorum(1, 1)), (false, false));
+    }
+
+    #[tokio::test]
+    async fn tcp_probe() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let kind = ProbeKind::Tcp(addr);
+        assert!(run(&kind, None, WINDOW).await.unwrap().is_some());
+
+        drop(listener);
+        assert!(run(&kind, None, WINDOW).await.is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn tcp_probe_times_out() {
+        use socket2::{Domain, Socket, Type};
+
+        // Once its backlog is full, the SYNs to the listener are dropped.
+        let listener = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        listener
+            .bind(
+                &"127.0.0.1:0"
+                    .parse::<std::net::SocketAddr>()
+                    .unwrap()
+                    .into(),
+            )
+            .unwrap();
+        listener.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap().as_socket().unwrap();
+        let timeout = Duration::from_millis(200);
+        let _queued: Vec<_> = (0..4)
+            .map_while(|_| std::net::TcpStream::connect_timeout(&addr, timeout).ok())
+            .collect();
+
+        assert_eq!(
+            run(&ProbeKind::Tcp(addr), None, timeout).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn http_probe() {
+        let url = http::tests::serve(Some("HTTP/1.0 302 Found\r\n\r\n")).await;
+        let rtt = run(&ProbeKind::Http(url), None, WINDOW).await.unwrap();
+        assert!(rtt.is_some());
+
+        let url = http::tests::serve(Some("HTTP/1.0 500 Oops\r\n\r\n")).await;
+        let rtt = run(&ProbeKind::Http(url), None, WINDOW).await.unwrap();
+        assert_eq!(rtt, None);
+    }
+
+    #[test]
+    fn keeps_the_latest_outcomes() {
+        let outcomes = outcomes(&[1]);
+        for _ in 0..KEPT_OUTCOMES {
+            outcomes.record(0, true);
+        }
+        outcomes.record(0, false);
+
+        let history = outcomes.history.lock().unwrap();
+        assert_eq!(history[0].len(), KEPT_OUTCOMES);
+        assert_eq!(history[0].back().map(|(_, success)| *success), Some(false));
+    }
+}
@@ -0,0 +1,48 @@
+use std::{io, net::SocketAddr};
+
+use pnet::datalink::NetworkInterface;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpSocket, TcpStream};
+
+use crate::netns;
+
+/// Connect to `addr` through the interface if any.
+pub(crate) async fn connect(
+    addr: SocketAddr,
+    interface: Option<&NetworkInterface>,
+) -> io::Result<TcpStream> {
+    let socket =
+        netns::run(|| Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP)))?;
+    if let Some(interface) = interface {
+        super::bind(&socket, interface, addr.ip())?;
+    }
+    socket.set_nonblocking(true)?;
+
+    TcpSocket::from_std_stream(socket.into())
+        .connect(addr)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = connect(listener.local_addr().unwrap(), None).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn refused() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let e = connect(addr, None).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,455 @@
+//! Availability reports (uptime, MTBF, MTTR...) computed from a Timeline.
+
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::history::{known, unavailable};
+use crate::{DayReport, Period, Report, ReportFormat, Timeline};
+
+const DAY: u64 = 86400;
+
+/// Serialize a Duration as seconds.
+pub(crate) mod secs {
+    use std::time::Duration;
+
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+}
+
+/// Serialize an Option<Duration> as seconds.
+pub(crate) mod opt_secs {
+    use std::time::Duration;
+
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Year, month and day of the days since the Unix epoch (proleptic Gregorian).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Days since the Unix epoch of the date, the inverse of civil_from_days.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn date(time: SystemTime) -> String {
+    let (year, month, day) = civil_from_days((unix_secs(time) / DAY) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn date_time(time: SystemTime) -> String {
+    let secs = unix_secs(time);
+    format!(
+        "{} {:02}:{:02}",
+        date(time),
+        secs / 3600 % 24,
+        secs / 60 % 60
+    )
+}
+
+/// The two most significant units of the duration, e.g. 3d 4h or 5m 6s.
+fn human(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / DAY, secs / 3600 % 24, secs / 60 % 60, secs % 60) {
+        (0, 0, 0, _) => format!("{:.1}s", duration.as_secs_f64()),
+        (0, 0, minutes, seconds) => format!("{}m {}s", minutes, seconds),
+        (0, hours, minutes, _) => format!("{}h {}m", hours, minutes),
+        (days, hours, _, _) => format!("{}d {}h", days, hours),
+    }
+}
+
+fn percent(availability: Option<f64>) -> String {
+    availability.map_or(String::from("-"), |a| format!("{:.3}%", a * 100.0))
+}
+
+fn ratio(part: Duration, whole: Duration) -> Option<f64> {
+    (!whole.is_zero()).then(|| 1.0 - part.as_secs_f64() / whole.as_secs_f64())
+}
+
+/// Monitored time, downtime and outages during `from`..`to`. The outages
+/// are counted where they start, those started before `since` at `since`,
+/// so that one spanning several days is counted once. So is one carried on
+/// after a restart, going on in the same state after the unmonitored gap.
+fn tally(
+    periods: &[Period],
+    since: SystemTime,
+    from: SystemTime,
+    to: SystemTime,
+) -> (Duration, Duration, u32) {
+    let mut monitored = Duration::ZERO;
+    let mut downtime = Duration::ZERO;
+    let mut outages = 0;
+    let mut previous = None;
+    for period in periods.iter().filter(|p| known(&p.state)) {
+        let overlap = period.overlap(from, to);
+        monitored += overlap;
+        if unavailable(&period.state) && !overlap.is_zero() {
+            downtime += overlap;
+            if period.start.max(since) >= from && previous != Some(&period.state) {
+                outages += 1;
+            }
+        }
+        previous = Some(&period.state);
+    }
+    (monitored, downtime, outages)
+}
+
+impl Timeline {
+    /// Availability of `interface` between `from` and `to`, with a
+    /// breakdown per day (UTC) for the days it was monitored.
+    pub fn report(&self, interface: &str, from: SystemTime, to: SystemTime) -> Report {
+        let periods = self.periods(Some(interface), from, to);
+        let (monitored, downtime, outages) = tally(&periods, from, from, to);
+        let longest_outage = periods
+            .iter()
+            .filter(|p| unavailable(&p.state) && !p.overlap(from, to).is_zero())
+            .map(|p| p.duration())
+            .max();
+
+        let mut days = Vec::new();
+        let mut day = UNIX_EPOCH + Duration::from_secs(unix_secs(from) / DAY * DAY);
+        while day < to {
+            let next = day + Duration::from_secs(DAY);
+            let (monitored, downtime, outages) = tally(&periods, from, day.max(from), next.min(to));
+            if !monitored.is_zero() {
+                days.push(DayReport {
+                    date: date(day),
+                    monitored,
+                    downtime,
+                    availability: ratio(downtime, monitored),
+                    outages,
+                });
+            }
+            day = next;
+        }
+
+        Report {
+            interface: interface.to_owned(),
+            from,
+            to,
+            monitored,
+            downtime,
+            availability: ratio(downtime, monitored),
+            outages,
+            mtbf: (outages > 0).then(|| monitored.saturating_sub(downtime) / outages),
+            mttr: (outages > 0).then(|| downtime / outages),
+            longest_outage,
+            days,
+        }
+    }
+}
+
+impl Report {
+    /// Midnight (UTC) of a YYYY-MM-DD date.
+    pub fn parse_date(date: &str) -> Result<SystemTime, anyhow::Error> {
+        let parse = || -> Option<SystemTime> {
+            let mut parts = date.splitn(3, '-');
+            let year: i64 = parts.next()?.parse().ok()?;
+            let month: u32 = parts.next()?.parse().ok()?;
+            let day: u32 = parts.next()?.parse().ok()?;
+            if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+                return None;
+            }
+            let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+            // Catch the days past the end of the month, e.g. 2026-02-30.
+            let time = UNIX_EPOCH + Duration::from_secs(days * DAY);
+            (civil_from_days(days as i64) == (year, month, day)).then_some(time)
+        };
+
+        parse().ok_or_else(|| anyhow::anyhow!("invalid date {:?}, expected YYYY-MM-DD", date))
+    }
+
+    /// Write the reports in the format.
+    pub fn render(reports: &[Report], format: ReportFormat) -> Result<String, anyhow::Error> {
+        let mut out = String::new();
+        match format {
+            ReportFormat::Json => {
+                out = serde_json::to_string_pretty(reports)?;
+                out.push('\n');
+            }
+            ReportFormat::Csv => {
+                out.push_str("interface,date,monitored_s,downtime_s,availability,outages,mtbf_s,mttr_s,longest_outage_s\n");
+                let secs =
+                    |d: Option<Duration>| d.map_or(String::new(), |d| d.as_secs().to_string());
+                let fraction = |a: Option<f64>| a.map_or(String::new(), |a| format!("{:.6}", a));
+                for report in reports {
+                    for day in &report.days {
+                        writeln!(
+                            out,
+                            "{},{},{},{},{},{},,,",
+                            report.interface,
+                            day.date,
+                            day.monitored.as_secs(),
+                            day.downtime.as_secs(),
+                            fraction(day.availability),
+                            day.outages
+                        )?;
+                    }
+                    writeln!(
+                        out,
+                        "{},total,{},{},{},{},{},{},{}",
+                        report.interface,
+                        report.monitored.as_secs(),
+                        report.downtime.as_secs(),
+                        fraction(report.availability),
+                        report.outages,
+                        secs(report.mtbf),
+                        secs(report.mttr),
+                        secs(report.longest_outage)
+                    )?;
+                }
+            }
+            ReportFormat::Table => {
+                let or_dash = |d: Option<Duration>| d.map_or(String::from("-"), human);
+                for report in reports {
+                    writeln!(
+                        out,
+                        "{}, {} to {} UTC",
+                        report.interface,
+                        date_time(report.from),
+                        date_time(report.to)
+                    )?;
+                    writeln!(
+                        out,
+                        "  {:<16}{}",
+                        "availability",
+                        percent(report.availability)
+                    )?;
+                    writeln!(out, "  {:<16}{}", "monitored", human(report.monitored))?;
+                    writeln!(out, "  {:<16}{}", "downtime", human(report.downtime))?;
+                    writeln!(out, "  {:<16}{}", "outages", report.outages)?;
+                    writeln!(out, "  {:<16}{}", "MTBF", or_dash(report.mtbf))?;
+                    writeln!(out, "  {:<16}{}", "MTTR", or_dash(report.mttr))?;
+                    writeln!(
+                        out,
+                        "  {:<16}{}",
+                        "longest outage",
+                        or_dash(report.longest_outage)
+                    )?;
+                    if !report.days.is_empty() {
+                        writeln!(
+                            out,
+                            "\n  {:<12}{:>13}{:>9}  downtime",
+                            "date", "availability", "outages"
+                        )?;
+                    }
+                    for day in &report.days {
+                        writeln!(
+                            out,
+                            "  {:<12}{:>13}{:>9}  {}",
+                            day.date,
+                            percent(day.availability),
+                            day.outages,
+                            human(day.downtime)
+                        )?;
+                    }
+                    out.push('\n');
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(ReportFormat::Table),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => anyhow::bail!("unknown format {:?}, expected table, json or csv", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, Transition};
+
+    const HOUR: u64 = 3600;
+
+    /// Hours after midnight (UTC) of 2026-01-01.
+    fn at(hours: u64) -> SystemTime {
+        Report::parse_date("2026-01-01").unwrap() + Duration::from_secs(hours * HOUR)
+    }
+
+    fn timeline(transitions: &[(u64, State)]) -> Timeline {
+        Timeline::new(
+            transitions
+                .iter()
+                .map(|(hours, state)| Transition {
+                    interface: String::from("eth0"),
+                    state: state.clone(),
+                    cause: None,
+                    at: at(*hours),
+                })
+                .collect(),
+        )
+    }
+
+    fn hours(hours: u64) -> Duration {
+        Duration::from_secs(hours * HOUR)
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(Report::parse_date("1970-01-01").unwrap(), UNIX_EPOCH);
+        assert_eq!(date(at(0)), "2026-01-01");
+        assert_eq!(date_time(at(26)), "2026-01-02 02:00");
+        assert_eq!(
+            date(Report::parse_date("2024-02-29").unwrap()),
+            "2024-02-29"
+        );
+        for invalid in ["2026-02-30", "2026-13-01", "2026-1", "1969-12-31", "today"] {
+            assert!(Report::parse_date(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn every_outage_is_downtime() {
+        let timeline = timeline(&[
+            (0, State::Up),
+            (1, State::Down),
+            (2, State::Degraded),
+            (3, State::Missing),
+            (4, State::Up),
+            (5, State::CaptivePortal),
+            (6, State::Up),
+        ]);
+        let report = timeline.report("eth0", at(0), at(10));
+
+        assert_eq!(report.monitored, hours(10));
+        assert_eq!(report.downtime, hours(3));
+        assert_eq!(report.outages, 3);
+        assert_eq!(report.availability, Some(0.7));
+        assert_eq!(report.mttr, Some(hours(1)));
+        assert_eq!(report.mtbf, Some(Duration::from_secs(7 * HOUR / 3)));
+        assert_eq!(report.longest_outage, Some(hours(1)));
+        assert_eq!(timeline.availability("eth0", at(0), at(10)), Some(0.7));
+        assert_eq!(timeline.outages(None, at(0), at(10)).len(), 3);
+    }
+
+    #[test]
+    fn outages_are_counted_the_day_they_start() {
+        // Down from 22:00 to 02:00 the next day, then from 10:00 to 11:00.
+        let timeline = timeline(&[
+            (0, State::Up),
+            (22, State::Down),
+            (26, State::Up),
+            (34, State::Down),
+            (35, State::Up),
+        ]);
+        let report = timeline.report("eth0", at(0), at(48));
+
+        assert_eq!(report.outages, 2);
+        assert_eq!(report.downtime, hours(5));
+        let days: Vec<_> = report
+            .days
+            .iter()
+            .map(|d| (d.date.as_str(), d.monitored, d.downtime, d.outages))
+            .collect();
+        assert_eq!(
+            days,
+            [
+                ("2026-01-01", hours(24), hours(2), 1),
+                ("2026-01-02", hours(24), hours(3), 1)
+            ]
+        );
+        assert_eq!(report.days.iter().map(|d| d.outages).sum::<u32>(), 2);
+    }
+
+    #[test]
+    fn outages_started_before_the_report() {
+        let timeline = timeline(&[(0, State::Up), (20, State::Down), (30, State::Up)]);
+        let report = timeline.report("eth0", at(24), at(48));
+
+        assert_eq!(report.outages, 1);
+        assert_eq!(report.downtime, hours(6));
+        assert_eq!(report.days[0].outages, 1);
+        // The whole outage, not only its part in the report.
+        assert_eq!(report.longest_outage, Some(hours(10)));
+    }
+
+    #[test]
+    fn outages_carried_on_after_a_restart() {
+        // Stopped while Down, and still Down once restarted.
+        let timeline = timeline(&[
+            (0, State::Up),
+            (1, State::Down),
+            (2, State::Ukn),
+            (3, State::Ukn),
+            (4, State::Down),
+            (5, State::Up),
+        ]);
+        let report = timeline.report("eth0", at(0), at(10));
+
+        assert_eq!(report.monitored, hours(8));
+        assert_eq!(report.downtime, hours(2));
+        assert_eq!(report.outages, 1);
+        assert_eq!(report.days[0].outages, 1);
+    }
+
+    #[test]
+    fn unmonitored() {
+        let timeline = timeline(&[(30, State::Up)]);
+        let report = timeline.report("eth0", at(0), at(48));
+
+        assert_eq!(report.monitored, hours(18));
+        assert_eq!(report.availability, Some(1.0));
+        assert_eq!(report.mtbf, None);
+        assert_eq!(report.days.len(), 1);
+        assert_eq!(timeline.report("wlan0", at(0), at(48)).availability, None);
+    }
+
+    #[test]
+    fn csv() {
+        let timeline = timeline(&[(0, State::Up), (1, State::Down), (2, State::Up)]);
+        let report = timeline.report("eth0", at(0), at(24));
+        let csv = Report::render(&[report], ReportFormat::Csv).unwrap();
+
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "eth0,2026-01-01,86400,3600,0.958333,1,,,");
+        assert_eq!(lines[2], "eth0,total,86400,3600,0.958333,1,82800,3600,3600");
+    }
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+use std::time::{Instant, SystemTime};
+
+/// Clock that keeps counting while suspended, and one that doesn't.
+#[cfg(target_os = "linux")]
+const CLOCKS: (libc::clockid_t, libc::clockid_t) = (libc::CLOCK_BOOTTIME, libc::CLOCK_MONOTONIC);
+/// mach_continuous_time and mach_absolute_time.
+#[cfg(target_os = "macos")]
+const CLOCKS: (libc::clockid_t, libc::clockid_t) =
+    (libc::CLOCK_MONOTONIC_RAW, libc::CLOCK_UPTIME_RAW);
+
+/// With the wall clock, the smallest jump taken for a suspend: NTP steps
+/// it (by more than 128ms) when it's too far off to be slewed.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const MIN_WALL_JUMP: Duration = Duration::from_secs(60);
+
+/// Detect suspend/resume cycles of the machine by looking at the divergence
+/// between a clock that keeps counting while suspended and one that doesn't.
+///
+/// On Linux this is CLOCK_BOOTTIME vs CLOCK_MONOTONIC, on macOS
+/// CLOCK_MONOTONIC_RAW vs CLOCK_UPTIME_RAW. Elsewhere we fall back to the
+/// wall clock vs the monotonic one, ignoring the jumps below MIN_WALL_JUMP.
+#[derive(Debug)]
+pub(crate) struct SuspendDetector {
+    offset: Duration,
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    start: (Instant, SystemTime),
+}
+
+impl SuspendDetector {
+    pub fn new() -> Self {
+        let mut detector = SuspendDetector {
+            offset: Duration::ZERO,
+            #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+            start: (Instant::now(), SystemTime::now()),
+        };
+        detector.offset = detector.clocks_offset();
+        detector
+    }
+
+    /// Return the time spent suspended since the last call if it's
+    /// bigger than `min`, None otherwise.
+    pub fn check(&mut self, min: Duration) -> Option<Duration> {
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        let min = min.max(MIN_WALL_JUMP);
+        let offset = self.clocks_offset();
+        let suspended = offset.saturating_sub(self.offset);
+        self.offset = offset;
+
+        if suspended > min {
+            Some(suspended)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn clocks_offset(&self) -> Duration {
+        clock_gettime(CLOCKS.0).saturating_sub(clock_gettime(CLOCKS.1))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn clocks_offset(&self) -> Duration {
+        let wall = SystemTime::now()
+            .duration_since(self.start.1)
+            .unwrap_or_default();
+
+        wall.saturating_sub(self.start.0.elapsed())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) fn clock_gettime(clock: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Can only fail with an invalid clock or pointer.
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
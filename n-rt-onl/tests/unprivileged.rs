@@ -0,0 +1,63 @@
+//! The monitor keeps working once the privileges are dropped,
+//! only run as root (e.g. `sudo -E cargo test --test unprivileged`).
+
+#![cfg(target_os = "linux")]
+
+use std::time::Duration;
+
+use n_rt_onl::{CheckStatus, Config, DropPrivileges, Onl, ProbeKind, ProbeTarget, State};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn monitors_after_the_drop() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipped, not running as root");
+        return;
+    }
+    let config = Config {
+        probes: vec![ProbeTarget::new(ProbeKind::Icmp(
+            "127.0.0.1".parse().unwrap(),
+        ))],
+        drop_privileges: Some(DropPrivileges {
+            user: String::from("nobody"),
+            group: None,
+            seccomp: false,
+        }),
+        ..Default::default()
+    };
+    #[cfg(not(feature = "userspace"))]
+    if !std::path::Path::new(&config.ebpf_prog_path).exists() {
+        eprintln!("skipped, the eBPF object isn't built");
+        return;
+    }
+
+    // The loopback works, only the local traffic goes through it.
+    let report = Onl::preflight("lo", &config);
+    let interface = report.checks.iter().find(|c| c.name == "interface");
+    assert_eq!(interface.map(|c| c.status), Some(CheckStatus::Warning));
+
+    let onl = Onl::new(String::from("lo"), Some(config)).unwrap();
+    let mut results = onl.probe_results();
+    let mut events = onl.start().unwrap();
+    assert_ne!(unsafe { libc::geteuid() }, 0);
+
+    // The probes still go out, and the maps (or the capture) can still be read.
+    let probe = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Ok(result) = results.recv().await {
+                if result.rtt.is_some() {
+                    break;
+                }
+            }
+        }
+    });
+    assert!(probe.await.is_ok(), "no successful probe after the drop");
+    let state = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            match events.recv().await.map(|e| e.state) {
+                Some(State::Ukn) => continue,
+                state => break state,
+            }
+        }
+    });
+    assert_eq!(state.await.unwrap(), Some(State::Up));
+}
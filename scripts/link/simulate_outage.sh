@@ -0,0 +1,3 @@
+#!/bin/bash
+
+ip link set dev wlo1 down
\ No newline at end of file
@@ -0,0 +1,15 @@
+#!/bin/bash
+
+case "$1" in
+link) ip -n onl-router link set dev rt0 up ;;
+gateway) ip -n onl-router addr add 10.200.0.1/24 dev rt0 ;;
+upstream) ip -n onl-router route replace default via 10.201.0.2 ;;
+target)
+	ip -n onl-upstream route del blackhole 203.0.113.2/32
+	ip -n onl-upstream addr add 203.0.113.2/32 dev lo
+	;;
+*)
+	echo "USAGE: $0 <link|gateway|upstream|target>"
+	exit 1
+	;;
+esac
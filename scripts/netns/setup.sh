@@ -0,0 +1,31 @@
+#!/bin/bash
+# Client (onl0) <-> fake router <-> upstream network with the targets
+# 203.0.113.1 and 203.0.113.2, run n-rt-onl in the client:
+#   ip netns exec onl-client n-rt-onl onl0
+
+set -e
+
+for ns in onl-client onl-router onl-upstream; do
+	ip netns add $ns
+	ip -n $ns link set dev lo up
+done
+
+ip link add onl0 netns onl-client type veth peer name rt0 netns onl-router
+ip link add up0 netns onl-router type veth peer name up1 netns onl-upstream
+
+ip -n onl-client addr add 10.200.0.2/24 dev onl0
+ip -n onl-client link set dev onl0 up
+ip -n onl-client route add default via 10.200.0.1
+
+ip -n onl-router addr add 10.200.0.1/24 dev rt0
+ip -n onl-router addr add 10.201.0.1/24 dev up0
+ip -n onl-router link set dev rt0 up
+ip -n onl-router link set dev up0 up
+ip -n onl-router route add default via 10.201.0.2
+ip netns exec onl-router sysctl -qw net.ipv4.ip_forward=1
+
+ip -n onl-upstream addr add 10.201.0.2/24 dev up1
+ip -n onl-upstream addr add 203.0.113.1/32 dev lo
+ip -n onl-upstream addr add 203.0.113.2/32 dev lo
+ip -n onl-upstream link set dev up1 up
+ip -n onl-upstream route add 10.200.0.0/24 via 10.201.0.1
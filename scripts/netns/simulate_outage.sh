@@ -0,0 +1,17 @@
+#!/bin/bash
+# Break the network set up by setup.sh at the given place:
+# link, gateway, upstream or target (203.0.113.2 only).
+
+case "$1" in
+link) ip -n onl-router link set dev rt0 down ;;
+gateway) ip -n onl-router addr del 10.200.0.1/24 dev rt0 ;;
+upstream) ip -n onl-router route replace blackhole default ;;
+target)
+	ip -n onl-upstream addr del 203.0.113.2/32 dev lo
+	ip -n onl-upstream route add blackhole 203.0.113.2/32
+	;;
+*)
+	echo "USAGE: $0 <link|gateway|upstream|target>"
+	exit 1
+	;;
+esac
@@ -0,0 +1,5 @@
+#!/bin/bash
+
+for ns in onl-client onl-router onl-upstream; do
+	ip netns del $ns
+done